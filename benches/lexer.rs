@@ -0,0 +1,62 @@
+//! Lexing throughput at increasing input sizes, up to 1 MB.
+//!
+//! Run with `cargo bench --bench lexer`. The time per byte should stay flat
+//! as the input grows; a quadratic lexer shows it growing with the size.
+
+use std::time::{Duration, Instant};
+
+use claylang::lexer::Lexer;
+
+const SNIPPET: &str = r#"
+fibonacci := |n| -> (n <= 1) match {
+    true -> n,
+    _ -> fibonacci(n - 1) + fibonacci(n - 2)
+}
+
+names := ["clay", "lexer", "bench", 3, 4.5]
+io.out(names)
+"#;
+
+const RUNS: usize = 5;
+
+fn source_of_size(bytes: usize) -> String {
+    let mut source = String::with_capacity(bytes + SNIPPET.len());
+    while source.len() < bytes {
+        source.push_str(SNIPPET);
+    }
+    source
+}
+
+fn time_lexing(source: &str) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut count = 0;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        count = Lexer::new(source).count();
+        best = best.min(start.elapsed());
+    }
+
+    (best, count)
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>10} {:>12} {:>10}",
+        "bytes", "tokens", "time", "ns/byte"
+    );
+
+    for kb in [64, 128, 256, 512, 1024] {
+        let source = source_of_size(kb * 1024);
+        let (elapsed, tokens) = time_lexing(&source);
+        let per_byte = elapsed.as_nanos() as f64 / source.len() as f64;
+
+        println!(
+            "{:>10} {:>10} {:>12.2?} {:>10.2}",
+            source.len(),
+            tokens,
+            elapsed,
+            per_byte
+        );
+    }
+}
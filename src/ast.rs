@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 
-use crate::token::Token;
+use crate::token::{Span, Token, TokenType};
 
 #[derive(Debug, Clone)]
 pub struct Program<'a> {
@@ -10,78 +10,223 @@ pub struct Program<'a> {
 pub enum Expression<'a> {
     DefinitionIdentifier {
         idents: Vec<Token<'a>>,
+        span: Span,
     },
     NormalIdentifier {
-        idents: Vec<Token<'a>>,
+        token: Token<'a>,
+        span: Span,
+    },
+    /// `object.name`
+    MemberAccess {
+        token: Token<'a>,
+        object: Box<Expression<'a>>,
+        name: Token<'a>,
+        span: Span,
     },
     StringLiteral {
         token: Token<'a>,
+        span: Span,
+    },
+    InterpolatedString {
+        token: Token<'a>,
+        parts: Vec<InterpolationPart<'a>>,
+        span: Span,
     },
     ArrayLiteral {
         token: Token<'a>,
         elements: Vec<Box<Expression<'a>>>,
+        span: Span,
     },
     BooleanLiteral {
         token: Token<'a>,
+        span: Span,
+    },
+    NilLiteral {
+        token: Token<'a>,
+        span: Span,
     },
     UnderscoreLiteral {
         token: Token<'a>,
+        span: Span,
     },
     IntegerLiteral {
         token: Token<'a>,
+        span: Span,
     },
     FloatLiteral {
         token: Token<'a>,
+        span: Span,
     },
     IndexExpression {
         token: Token<'a>,
         left: Box<Expression<'a>>,
         index: Box<Expression<'a>>,
+        span: Span,
+    },
+    /// `left[start:end:step]`, where each bound may be left out.
+    SliceExpression {
+        token: Token<'a>,
+        left: Box<Expression<'a>>,
+        start: Option<Box<Expression<'a>>>,
+        end: Option<Box<Expression<'a>>>,
+        step: Option<Box<Expression<'a>>>,
+        span: Span,
     },
     MapLiteral {
         token: Token<'a>,
-        pairs: HashMap<Box<Expression<'a>>, Box<Expression<'a>>>,
+        /// Key/value pairs in source order.
+        pairs: Vec<(MapKey<'a>, Box<Expression<'a>>)>,
+        span: Span,
     },
     FunctionLiteral {
         token: Token<'a>,
         parameters: Box<Expression<'a>>,
-        statements: Statement<'a>,
+        statements: Box<Statement<'a>>,
+        span: Span,
     },
     CallExpression {
         token: Token<'a>,
         parameters: Vec<Box<Expression<'a>>>,
         function: Box<Expression<'a>>,
+        span: Span,
     },
 
     PrefixExpression {
         token: Token<'a>,
         right: Box<Expression<'a>>,
+        span: Span,
     },
 
     InfixExpression {
         token: Token<'a>,
         right: Box<Expression<'a>>,
         left: Box<Expression<'a>>,
+        span: Span,
     },
 
     IfExpression {
         token: Token<'a>,
         condition: Box<Expression<'a>>,
-        consequence: Statement<'a>,
-        alternative: Statement<'a>,
+        consequence: Box<Statement<'a>>,
+        /// The `else` block, or for `else if` an expression statement holding
+        /// the nested `IfExpression`. Without an `else`, the value is nil.
+        alternative: Option<Box<Statement<'a>>>,
+        span: Span,
     },
 
     MatchExpression {
         token: Token<'a>,
-        default: Option<Statement<'a>>,
+        default: Option<Box<Statement<'a>>>,
         pairs: Vec<MatchPairExpression<'a>>,
+        span: Span,
+    },
+
+    /// Stands in for an expression that failed to parse.
+    Error {
+        span: Span,
     },
 }
 
+/// A piece of an interpolated string, in source order. Empty literal text
+/// between interpolations is left out.
+#[derive(Debug, Clone)]
+pub enum InterpolationPart<'a> {
+    Literal(Cow<'a, str>),
+    Expression(Box<Expression<'a>>),
+}
+
+/// The key of a map literal entry.
+#[derive(Debug, Clone)]
+pub enum MapKey<'a> {
+    /// `name: value` or `"name": value`, a key known while parsing. `token`
+    /// is the identifier or string it was written as.
+    Constant {
+        name: Cow<'a, str>,
+        token: Token<'a>,
+    },
+    /// `[expression]: value`, a key computed when the map is built.
+    Computed(Box<Expression<'a>>),
+}
+
+impl<'a> MapKey<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            MapKey::Constant { token, .. } => token.span,
+            MapKey::Computed(expression) => expression.span(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MatchPairExpression<'a> {
     pub statement: Statement<'a>,
-    pub predicate: Vec<Box<Expression<'a>>>,
+    pub pattern: Pattern<'a>,
+    /// The condition after `if`, checked once the pattern has matched.
+    pub guard: Option<Box<Expression<'a>>>,
+    pub span: Span,
+}
+
+/// The shape a value is tested against in a match arm.
+#[derive(Debug, Clone)]
+pub enum Pattern<'a> {
+    /// A number, string, boolean or nil the value must equal. Numbers may be
+    /// negated.
+    Literal(Box<Expression<'a>>),
+    /// A name, bound to the value.
+    Binding(Token<'a>),
+    /// `_`, matching anything.
+    Wildcard(Token<'a>),
+    /// `[first, second]`, where one element may be a `Rest` pattern taking
+    /// the elements the others leave over.
+    Array {
+        token: Token<'a>,
+        elements: Vec<Pattern<'a>>,
+        span: Span,
+    },
+    /// `...rest` or `...`, only found directly inside an array pattern.
+    Rest {
+        token: Token<'a>,
+        name: Option<Token<'a>>,
+        span: Span,
+    },
+    /// `{ name: pattern, "key": pattern, shorthand }`, matching maps that
+    /// have at least these keys. Keys are always `MapKey::Constant`, and
+    /// `shorthand` is short for `shorthand: shorthand`.
+    Map {
+        token: Token<'a>,
+        fields: Vec<(MapKey<'a>, Pattern<'a>)>,
+        span: Span,
+    },
+    /// `start..end`, or `start..=end` when `inclusive`.
+    Range {
+        token: Token<'a>,
+        start: Box<Expression<'a>>,
+        end: Box<Expression<'a>>,
+        inclusive: bool,
+        span: Span,
+    },
+    /// `a | b`, matching when any alternative does.
+    Or {
+        alternatives: Vec<Pattern<'a>>,
+        span: Span,
+    },
+    /// Stands in for a pattern that failed to parse.
+    Error { span: Span },
+}
+
+impl<'a> Pattern<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Literal(expression) => expression.span(),
+            Pattern::Binding(token) | Pattern::Wildcard(token) => token.span,
+            Pattern::Array { span, .. }
+            | Pattern::Rest { span, .. }
+            | Pattern::Map { span, .. }
+            | Pattern::Range { span, .. }
+            | Pattern::Or { span, .. }
+            | Pattern::Error { span } => *span,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -89,28 +234,128 @@ pub enum Statement<'a> {
     BlockStatement {
         token: Token<'a>,
         statements: Vec<Statement<'a>>,
+        span: Span,
     },
     ExpressionStatement {
         token: Token<'a>,
         expression: Box<Expression<'a>>,
+        span: Span,
     },
     AssignStatement {
         token: Token<'a>,
         expression: Box<Expression<'a>>,
         defined: Box<Expression<'a>>,
+        span: Span,
     },
 
+    /// `target = value` or `target op= value`, where `token` is the
+    /// assignment operator. The target is a name, a member access or an
+    /// index expression.
     UpdateStatement {
         token: Token<'a>,
+        /// The binary operator a compound assignment applies, such as `Plus`
+        /// for `+=`, or `None` for `=`.
+        operator: Option<TokenType<'a>>,
         ident: Box<Expression<'a>>,
         expression: Box<Expression<'a>>,
+        span: Span,
     },
+    /// `return value`, or a bare `return`.
     ReturnStatement {
         token: Token<'a>,
-        value: Box<Expression<'a>>,
+        value: Option<Box<Expression<'a>>>,
+        span: Span,
+    },
+    /// `label: while condition { ... }`, where the label is optional, as it
+    /// is for the other loops.
+    WhileStatement {
+        token: Token<'a>,
+        label: Option<Token<'a>>,
+        condition: Box<Expression<'a>>,
+        body: Box<Statement<'a>>,
+        span: Span,
+    },
+    /// `for x in xs { ... }`, or `for k, v in map { ... }` binding a key and
+    /// a value.
+    ForStatement {
+        token: Token<'a>,
+        label: Option<Token<'a>>,
+        variables: Box<Expression<'a>>,
+        iterable: Box<Expression<'a>>,
+        body: Box<Statement<'a>>,
+        span: Span,
+    },
+    LoopStatement {
+        token: Token<'a>,
+        label: Option<Token<'a>>,
+        body: Box<Statement<'a>>,
+        span: Span,
+    },
+    /// `break`, `break label`, `break value` or `break label value`.
+    BreakStatement {
+        token: Token<'a>,
+        label: Option<Token<'a>>,
+        value: Option<Box<Expression<'a>>>,
+        span: Span,
+    },
+    ContinueStatement {
+        token: Token<'a>,
+        label: Option<Token<'a>>,
+        span: Span,
     },
     ImportStatement {
         token: Token<'a>,
         value: Token<'a>,
+        span: Span,
     },
+
+    /// Stands in for a statement that failed to parse.
+    Error { span: Span },
+}
+
+impl<'a> Expression<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::DefinitionIdentifier { span, .. }
+            | Expression::NormalIdentifier { span, .. }
+            | Expression::MemberAccess { span, .. }
+            | Expression::StringLiteral { span, .. }
+            | Expression::InterpolatedString { span, .. }
+            | Expression::ArrayLiteral { span, .. }
+            | Expression::BooleanLiteral { span, .. }
+            | Expression::NilLiteral { span, .. }
+            | Expression::UnderscoreLiteral { span, .. }
+            | Expression::IntegerLiteral { span, .. }
+            | Expression::FloatLiteral { span, .. }
+            | Expression::IndexExpression { span, .. }
+            | Expression::SliceExpression { span, .. }
+            | Expression::MapLiteral { span, .. }
+            | Expression::FunctionLiteral { span, .. }
+            | Expression::CallExpression { span, .. }
+            | Expression::PrefixExpression { span, .. }
+            | Expression::InfixExpression { span, .. }
+            | Expression::IfExpression { span, .. }
+            | Expression::MatchExpression { span, .. }
+            | Expression::Error { span } => *span,
+        }
+    }
+}
+
+impl<'a> Statement<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::BlockStatement { span, .. }
+            | Statement::ExpressionStatement { span, .. }
+            | Statement::AssignStatement { span, .. }
+            | Statement::UpdateStatement { span, .. }
+            | Statement::ReturnStatement { span, .. }
+            | Statement::WhileStatement { span, .. }
+            | Statement::ForStatement { span, .. }
+            | Statement::LoopStatement { span, .. }
+            | Statement::BreakStatement { span, .. }
+            | Statement::ContinueStatement { span, .. }
+            | Statement::ImportStatement { span, .. }
+            | Statement::Error { span } => *span,
+        }
+    }
 }
@@ -1,8 +1,97 @@
-use std::process::exit;
+use std::{fmt, process::exit};
 
 use colored::*;
 
-pub fn error(err: String) {
+use crate::token::Span;
+
+pub fn report<T: fmt::Display>(err: T) {
     println!("{} {}", "[ERROR]".black().on_red(), err);
+}
+
+/// Like `report`, but on stderr, for commands whose stdout is their output.
+pub fn report_stderr<T: fmt::Display>(err: T) {
+    eprintln!("{} {}", "[ERROR]".black().on_red(), err);
+}
+
+pub fn error(err: String) {
+    report(err);
     exit(1);
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    IllegalCharacter(char),
+    InvalidInteger(String),
+    InvalidFloat(String),
+    IntegerOverflow(String),
+    UnterminatedString,
+    InvalidEscape(String),
+    UnterminatedComment,
+}
+
+#[derive(Debug, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl LexError {
+    pub fn new(kind: LexErrorKind, span: Span) -> LexError {
+        LexError { kind, span }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            LexErrorKind::IllegalCharacter(ch) => {
+                write!(f, "{} Found illegal character '{}'.", self.span, ch)
+            }
+            LexErrorKind::InvalidInteger(num) => {
+                write!(f, "{} Could not parse '{}' as an integer.", self.span, num)
+            }
+            LexErrorKind::InvalidFloat(num) => {
+                write!(f, "{} Could not parse '{}' as a float.", self.span, num)
+            }
+            LexErrorKind::IntegerOverflow(num) => write!(
+                f,
+                "{} The integer '{}' does not fit in 64 bits.",
+                self.span, num
+            ),
+            LexErrorKind::UnterminatedString => write!(
+                f,
+                "{} This string is never closed; add the missing quote.",
+                self.span
+            ),
+            LexErrorKind::InvalidEscape(escape) => write!(
+                f,
+                "{} Unknown escape sequence '{}' in string.",
+                self.span, escape
+            ),
+            LexErrorKind::UnterminatedComment => write!(
+                f,
+                "{} This block comment is never closed; add the missing '*/'.",
+                self.span
+            ),
+        }
+    }
+}
+
+/// A problem found while parsing. The parser records it and carries on.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: String, span: Span) -> ParseError {
+        ParseError { message, span }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.span, self.message)
+    }
+}
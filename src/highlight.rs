@@ -0,0 +1,346 @@
+use colored::Colorize;
+
+use crate::{
+    errors::LexError,
+    lexer::Lexer,
+    token::{Position, Span, TokenType},
+};
+
+/// The style class a piece of highlighted source is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenClass {
+    Keyword,
+    Literal,
+    Operator,
+    Punctuation,
+    Identifier,
+    Comment,
+    /// Whitespace and newlines.
+    Plain,
+    /// Source the lexer could not make sense of.
+    Error,
+}
+
+impl TokenClass {
+    pub fn classify(kind: &TokenType) -> TokenClass {
+        match kind {
+            TokenType::Match
+            | TokenType::If
+            | TokenType::Else
+            | TokenType::While
+            | TokenType::For
+            | TokenType::In
+            | TokenType::Loop
+            | TokenType::Break
+            | TokenType::Continue
+            | TokenType::Import
+            | TokenType::Return => TokenClass::Keyword,
+            TokenType::Integer(_)
+            | TokenType::Float(_)
+            | TokenType::String(_)
+            | TokenType::StringHead(_)
+            | TokenType::StringMiddle(_)
+            | TokenType::StringTail(_)
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil => TokenClass::Literal,
+            TokenType::Ident(_) | TokenType::Underscore => TokenClass::Identifier,
+            TokenType::Comment(_) => TokenClass::Comment,
+            TokenType::Whitespace(_) | TokenType::Newline => TokenClass::Plain,
+            TokenType::RParen
+            | TokenType::LParen
+            | TokenType::RBrace
+            | TokenType::LBrace
+            | TokenType::RBracket
+            | TokenType::LBracket
+            | TokenType::Period
+            | TokenType::Semicolon
+            | TokenType::Comma
+            | TokenType::Colon => TokenClass::Punctuation,
+            TokenType::Percent
+            | TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Slash
+            | TokenType::Asterisk
+            | TokenType::DoubleAsterisk
+            | TokenType::Equal
+            | TokenType::DoubleEqual
+            | TokenType::Bang
+            | TokenType::BangEqual
+            | TokenType::LT
+            | TokenType::LTEq
+            | TokenType::GT
+            | TokenType::GTEq
+            | TokenType::Arrow
+            | TokenType::Ampersand
+            | TokenType::And
+            | TokenType::Bar
+            | TokenType::Or
+            | TokenType::Caret
+            | TokenType::Tilde
+            | TokenType::DotDot
+            | TokenType::DotDotEqual
+            | TokenType::Ellipsis
+            | TokenType::ShiftLeft
+            | TokenType::ShiftRight
+            | TokenType::PlusEqual
+            | TokenType::MinusEqual
+            | TokenType::SlashEqual
+            | TokenType::AsteriskEqual
+            | TokenType::PercentEqual
+            | TokenType::ColonEqual => TokenClass::Operator,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Literal => "literal",
+            TokenClass::Operator => "operator",
+            TokenClass::Punctuation => "punctuation",
+            TokenClass::Identifier => "identifier",
+            TokenClass::Comment => "comment",
+            TokenClass::Plain => "plain",
+            TokenClass::Error => "error",
+        }
+    }
+}
+
+/// A run of source text drawn in a single style.
+#[derive(Debug, Clone)]
+pub struct Segment<'a> {
+    pub class: TokenClass,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ansi,
+    Html,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "ansi" => Some(Format::Ansi),
+            "html" => Some(Format::Html),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Splits `source` into styled segments that together cover it byte for
+/// byte. Input the lexer rejects becomes an `Error` segment instead of
+/// stopping the highlighter; the rejections are returned alongside.
+pub fn segments(source: &str) -> (Vec<Segment<'_>>, Vec<LexError>) {
+    let mut segments = Vec::new();
+    let mut errors = Vec::new();
+    let mut position = Position::new(1, 0, 0, 0, 0);
+
+    for result in Lexer::new(source).with_trivia() {
+        let token = match result {
+            Ok(token) => token,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+
+        if token.span.start.offset > position.offset {
+            segments.push(error_segment(source, position, token.span.start.offset));
+        }
+        segments.push(Segment {
+            class: TokenClass::classify(&token.kind),
+            text: token.span.source_text(source),
+            span: token.span,
+        });
+        position = token.span.end;
+    }
+
+    if position.offset < source.len() {
+        segments.push(error_segment(source, position, source.len()));
+    }
+
+    (segments, errors)
+}
+
+/// The segment for the unlexable text from `start` up to byte offset `end`.
+fn error_segment(source: &str, start: Position, end: usize) -> Segment<'_> {
+    let text = &source[start.offset..end];
+    let mut position = start;
+    for ch in text.chars() {
+        position.offset += ch.len_utf8();
+        position.char += 1;
+        if ch == '\n' {
+            position.line += 1;
+            position.column = 0;
+            position.utf16_column = 0;
+        } else {
+            position.column += 1;
+            position.utf16_column += ch.len_utf16();
+        }
+    }
+
+    Segment {
+        class: TokenClass::Error,
+        text,
+        span: Span::new(0, start, position),
+    }
+}
+
+pub fn render(segments: &[Segment], format: Format) -> String {
+    match format {
+        Format::Ansi => render_ansi(segments),
+        Format::Html => render_html(segments),
+        Format::Json => render_json(segments),
+    }
+}
+
+fn render_ansi(segments: &[Segment]) -> String {
+    let mut out = String::new();
+    for segment in segments {
+        let text = segment.text;
+        let styled = match segment.class {
+            TokenClass::Keyword => text.magenta().bold(),
+            TokenClass::Literal => text.green(),
+            TokenClass::Operator => text.yellow(),
+            TokenClass::Punctuation | TokenClass::Plain => text.normal(),
+            TokenClass::Identifier => text.cyan(),
+            TokenClass::Comment => text.bright_black().italic(),
+            TokenClass::Error => text.red().underline(),
+        };
+        out.push_str(&styled.to_string());
+    }
+    out
+}
+
+fn render_html(segments: &[Segment]) -> String {
+    let mut out = String::from("<pre class=\"clay\"><code>");
+    for segment in segments {
+        match segment.class {
+            TokenClass::Plain => escape_html(segment.text, &mut out),
+            class => {
+                out.push_str(&format!("<span class=\"clay-{}\">", class.name()));
+                escape_html(segment.text, &mut out);
+                out.push_str("</span>");
+            }
+        }
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            ch => out.push(ch),
+        }
+    }
+}
+
+/// One object per segment, with `[line, column]` positions where columns
+/// count chars, as in `Position`.
+fn render_json(segments: &[Segment]) -> String {
+    let mut out = String::from("[");
+    for (index, segment) in segments.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        let (start, end) = (segment.span.start, segment.span.end);
+        out.push_str(&format!(
+            "\n  {{\"class\": \"{}\", \"text\": ",
+            segment.class.name()
+        ));
+        escape_json(segment.text, &mut out);
+        out.push_str(&format!(
+            ", \"start\": [{}, {}], \"end\": [{}, {}]}}",
+            start.line, start.column, end.line, end.column
+        ));
+    }
+    out.push_str("\n]\n");
+    out
+}
+
+fn escape_json(text: &str, out: &mut String) {
+    out.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_cover_the_source() {
+        let source = "x := \"a {b}\" + 1 // note\n$ f(x) /* open";
+        let (segments, errors) = segments(source);
+
+        let text = segments.iter().map(|s| s.text).collect::<String>();
+        assert_eq!(text, source);
+        assert_eq!(errors.len(), 2);
+
+        let classes = segments
+            .iter()
+            .filter(|s| s.class != TokenClass::Plain)
+            .map(|s| (s.class, s.text))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            vec![
+                (TokenClass::Identifier, "x"),
+                (TokenClass::Operator, ":="),
+                (TokenClass::Literal, "\"a {"),
+                (TokenClass::Identifier, "b"),
+                (TokenClass::Literal, "}\""),
+                (TokenClass::Operator, "+"),
+                (TokenClass::Literal, "1"),
+                (TokenClass::Comment, "// note"),
+                (TokenClass::Error, "$"),
+                (TokenClass::Identifier, "f"),
+                (TokenClass::Punctuation, "("),
+                (TokenClass::Identifier, "x"),
+                (TokenClass::Punctuation, ")"),
+                (TokenClass::Error, "/* open"),
+            ]
+        );
+
+        let open = segments.last().unwrap().span;
+        assert_eq!((open.start.line, open.start.column), (2, 7));
+        assert_eq!((open.end.line, open.end.column), (2, 14));
+    }
+
+    #[test]
+    fn html_and_json_are_escaped() {
+        let (segments, _) = segments("a < \"\\\"<b>\"");
+
+        assert_eq!(
+            render(&segments, Format::Html),
+            "<pre class=\"clay\"><code><span class=\"clay-identifier\">a</span> \
+             <span class=\"clay-operator\">&lt;</span> \
+             <span class=\"clay-literal\">&quot;\\&quot;&lt;b&gt;&quot;</span>\
+             </code></pre>\n"
+        );
+
+        let json = render(&segments, Format::Json);
+        assert!(json.starts_with("[\n  {\"class\": \"identifier\", \"text\": \"a\", \"start\": [1, 0], \"end\": [1, 1]},"));
+        assert!(json.contains("\"text\": \"\\\"\\\\\\\"<b>\\\"\""));
+    }
+}
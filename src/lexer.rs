@@ -1,75 +1,994 @@
-use std::usize;
+use std::{borrow::Cow, num::IntErrorKind, ops::Range};
+
+use unicode_xid::UnicodeXID;
 
 use crate::{
-    errors::error,
-    token::{Position, Token, TokenType},
+    errors::{LexError, LexErrorKind},
+    token::{Position, Span, Token, TokenType},
 };
 
+/// A change to a source text: the bytes in `range` are replaced by `text`.
+#[derive(Debug, Clone)]
+pub struct TextEdit<'e> {
+    pub range: Range<usize>,
+    pub text: &'e str,
+}
+
+/// Which tokens an incremental re-lex replaced: `removed` old tokens starting
+/// at index `start` were replaced by `inserted` new ones at the same index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenDiff {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+#[derive(Debug)]
+pub struct Relexed<'a> {
+    pub tokens: Vec<Token<'a>>,
+    /// Errors from the re-lexed region only. Errors before and after it are
+    /// not carried over, and those after it would have moved, so callers that
+    /// need every diagnostic must lex the whole input again.
+    pub errors: Vec<LexError>,
+    pub diff: TokenDiff,
+}
+
+/// What the lexer needs to know to decide whether a newline ends a statement.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct StatementState {
+    /// The brackets opened and not yet closed, innermost last.
+    brackets: Vec<char>,
+    /// Whether the last token can end a statement.
+    can_end: bool,
+    /// Whether the last token ends an operand, making a `-` after it binary.
+    after_operand: bool,
+}
+
+impl StatementState {
+    fn track(&mut self, kind: &TokenType) {
+        match kind {
+            TokenType::LParen => self.brackets.push('('),
+            TokenType::LBracket => self.brackets.push('['),
+            TokenType::LBrace => self.brackets.push('{'),
+            TokenType::RParen | TokenType::RBracket | TokenType::RBrace => {
+                self.brackets.pop();
+            }
+            TokenType::Comment(_) | TokenType::Whitespace(_) => return,
+            _ => {}
+        }
+        self.can_end = kind.can_end_statement();
+        self.after_operand = kind.ends_operand();
+    }
+}
+
+/// The cursor is a byte offset into `input` (`position.offset`), so every
+/// character lookup is constant time and the lexer runs in linear time.
 pub struct Lexer<'a> {
     input: &'a str,
     position: Position,
+    file_id: usize,
+    keep_comments: bool,
+    keep_trivia: bool,
+    /// One entry per interpolation the cursor is inside of: where its string
+    /// started, and how many `{` have been opened in it and not yet closed.
+    interpolations: Vec<(Position, usize)>,
+    statement: StatementState,
+    /// Where the code after the last newline checked starts, and whether it
+    /// starts with a `.`. See `next_code_starts_with_period`.
+    lookahead: Option<(usize, bool)>,
+    /// In trivia mode, an error found inside the last token yielded, such as
+    /// a bad escape in a string, reported right after that token.
+    pending_error: Option<LexError>,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &'a str) -> Lexer {
+    pub fn new(input: &'a str) -> Lexer<'a> {
         Lexer {
             input,
-            position: Position::new(1, 0, 0),
+            position: Position::new(1, 0, 0, 0, 0),
+            file_id: 0,
+            keep_comments: false,
+            keep_trivia: false,
+            interpolations: Vec::new(),
+            statement: StatementState::default(),
+            lookahead: None,
+            pending_error: None,
         }
     }
 
+    /// Makes the lexer yield `Comment` tokens instead of skipping them.
+    pub fn with_comments(mut self) -> Lexer<'a> {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Makes the lexer lossless: whitespace, newlines and comments are all
+    /// yielded as tokens, so concatenating the source text of every token
+    /// reproduces the input. Input that fails to lex is reported as an error
+    /// and is exactly the text missing between the surrounding tokens. The
+    /// one exception is a bad escape sequence: in this mode its string is
+    /// still yielded as a token, followed by an error pointing inside it.
+    pub fn with_trivia(mut self) -> Lexer<'a> {
+        self.keep_comments = true;
+        self.keep_trivia = true;
+        self
+    }
+
+    /// Sets the file id recorded in every span this lexer produces.
+    pub fn with_file_id(mut self, file_id: usize) -> Lexer<'a> {
+        self.file_id = file_id;
+        self
+    }
+
+    /// The span from `start` up to the current position.
+    pub fn span_from(&self, start: Position) -> Span {
+        Span::new(self.file_id, start, self.position)
+    }
+
     pub fn consume_char(&mut self) {
+        if let Some(ch) = self.get_current_char() {
+            self.position.offset += ch.len_utf8();
+            self.position.utf16_column += ch.len_utf16();
+        }
         self.position.column += 1;
         self.position.char += 1;
     }
 
-    pub fn get_nth_char(&self, position: usize) -> Option<char> {
-        return self.input.chars().nth(position);
+    pub fn consume_newline(&mut self) {
+        self.consume_char();
+        self.position.line += 1;
+        self.position.column = 0;
+        self.position.utf16_column = 0;
     }
 
     pub fn get_current_char(&self) -> Option<char> {
-        return self.input.chars().nth(self.position.char);
+        return self.input[self.position.offset..].chars().next();
     }
 
     pub fn get_peek_char(&self) -> Option<char> {
-        return self.input.chars().nth(self.position.char + 1);
+        return self.input[self.position.offset..].chars().nth(1);
+    }
+
+    /// Lexes the whitespace, newlines and comments under the cursor, returning
+    /// the first one the lexer has been asked to keep, if any.
+    fn lex_trivia(&mut self) -> Option<Result<Token<'a>, LexError>> {
+        loop {
+            match self.get_current_char() {
+                Some(' ') | Some('\t') | Some('\r') => {
+                    let start = self.position;
+                    while matches!(self.get_current_char(), Some(' ' | '\t' | '\r')) {
+                        self.consume_char();
+                    }
+                    if self.keep_trivia {
+                        return Some(Ok(Token {
+                            kind: TokenType::Whitespace(
+                                &self.input[start.offset..self.position.offset],
+                            ),
+                            span: self.span_from(start),
+                        }));
+                    }
+                }
+                Some('\n') => {
+                    let start = self.position;
+                    let significant = self.newline_ends_statement();
+                    self.consume_newline();
+                    if self.keep_trivia || significant {
+                        return Some(Ok(Token {
+                            kind: TokenType::Newline,
+                            span: self.span_from(start),
+                        }));
+                    }
+                }
+                Some('#') if self.position.offset == 0 && self.starts_with("#!") => {
+                    // A shebang line, as in `#!/usr/bin/env clay`. It is kept
+                    // as a comment; the newline ending it is left in place.
+                    let start = self.position;
+                    while !matches!(self.get_current_char(), None | Some('\n')) {
+                        self.consume_char();
+                    }
+                    if self.keep_comments {
+                        return Some(Ok(Token {
+                            kind: TokenType::Comment(&self.input[..self.position.offset]),
+                            span: self.span_from(start),
+                        }));
+                    }
+                }
+                Some('/') if self.starts_with("//") || self.starts_with("/*") => {
+                    let comment = self.lex_comment();
+                    if self.keep_comments || comment.is_err() {
+                        return Some(comment);
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Whether the newline under the cursor ends a statement: it does when
+    /// it follows a token that can end one, outside any parentheses, brackets
+    /// or interpolation, and the next line does not start with a `.`.
+    /// Comments between the newline and the `.` are skipped.
+    fn newline_ends_statement(&mut self) -> bool {
+        return self.statement.can_end
+            && !matches!(self.statement.brackets.last(), Some('(') | Some('['))
+            && self.interpolations.is_empty()
+            && !self.next_code_starts_with_period();
+    }
+
+    /// Whether the first code after the cursor, past whitespace and comments,
+    /// is a `.`. The answer holds at every newline up to that code, so it is
+    /// kept until the cursor reaches it rather than rescanning the comments.
+    fn next_code_starts_with_period(&mut self) -> bool {
+        if let Some((code, period)) = self.lookahead {
+            if self.position.offset < code {
+                return period;
+            }
+        }
+
+        let mut code = self.position.offset;
+        loop {
+            let rest = &self.input[code..];
+            let trimmed = rest.trim_start_matches([' ', '\t', '\r', '\n']);
+            code += rest.len() - trimmed.len();
+            if !trimmed.starts_with("//") && !trimmed.starts_with("/*") {
+                break;
+            }
+            match comment_len(trimmed) {
+                Some(len) => code += len,
+                None => {
+                    code = self.input.len();
+                    break;
+                }
+            }
+        }
+
+        let period = self.input[code..].starts_with('.');
+        self.lookahead = Some((code, period));
+        period
+    }
+
+    pub fn lex_single_char<'b>(
+        &mut self,
+        kind: TokenType<'b>,
+    ) -> Option<Result<Token<'b>, LexError>> {
+        let start = self.position;
+        self.consume_char();
+        return Some(Ok(Token {
+            kind,
+            span: self.span_from(start),
+        }));
     }
 
-    pub fn lex_single_char<'b>(&mut self, kind: TokenType<'b>) -> Option<Token<'b>> {
-        let position = self.position;
+    pub fn lex_double_char<'b>(
+        &mut self,
+        kind: TokenType<'b>,
+    ) -> Option<Result<Token<'b>, LexError>> {
+        let start = self.position;
         self.consume_char();
-        return Some(Token { position, kind });
+        self.consume_char();
+        Some(Ok(Token {
+            kind,
+            span: self.span_from(start),
+        }))
     }
 
-    pub fn lex_double_char<'b>(&mut self, kind: TokenType<'b>) -> Option<Token<'b>> {
-        let position = self.position;
+    pub fn lex_triple_char<'b>(
+        &mut self,
+        kind: TokenType<'b>,
+    ) -> Option<Result<Token<'b>, LexError>> {
+        let start = self.position;
+        self.consume_char();
         self.consume_char();
         self.consume_char();
-        Some(Token { kind, position })
+        Some(Ok(Token {
+            kind,
+            span: self.span_from(start),
+        }))
+    }
+
+    /// Re-lexes only the part of the input affected by `edit`, reusing
+    /// `old_tokens` (lexed with the same settings as this lexer) everywhere
+    /// else. This lexer's input must be the old source with the edit applied.
+    ///
+    /// Lexing restarts at the token before the edit and stops as soon as a new
+    /// token lines up with an old one past the edit; the remaining old tokens
+    /// are shifted into place without being lexed again.
+    pub fn relex(mut self, old_tokens: &[Token], edit: &TextEdit) -> Relexed<'a> {
+        let input = self.input;
+        let edit_end = edit.range.start + edit.text.len();
+        let delta = edit_end as isize - edit.range.end as isize;
+
+        // Tokens ending before the edit are kept, except the last one, which
+        // the edit may extend (`ab` followed by an inserted `c`).
+        let mut first = old_tokens
+            .partition_point(|token| token.span.end.offset < edit.range.start)
+            .saturating_sub(1);
+
+        // The lexer restarts with no open interpolations, so restart before
+        // the string that encloses the edit, if any.
+        let mut depth = 0;
+        let mut outside = 0;
+        for (index, token) in old_tokens[..first].iter().enumerate() {
+            if depth == 0 {
+                outside = index;
+            }
+            depth += interpolation_depth_change(&token.kind);
+        }
+        if depth != 0 {
+            first = outside;
+        }
+        // With nothing kept, lex from the very start: the first token may
+        // come after the edit.
+        if first > 0 {
+            self.position = old_tokens[first].span.start;
+        }
+        for token in &old_tokens[..first] {
+            self.statement.track(&token.kind);
+        }
+
+        // What a token borrows sits inside its span, between its delimiters:
+        // none for names and trivia, quotes or braces for string contents.
+        // Only a raw string's `r` makes the front longer, so the front is half
+        // the delimiters, rounded up.
+        let reborrow = |span: &Span, text: &str, shift: isize| {
+            let delimiters = span.end.offset - span.start.offset - text.len();
+            let start = (span.start.offset as isize + shift) as usize + delimiters.div_ceil(2);
+            &input[start..start + text.len()]
+        };
+
+        let mut tokens = old_tokens[..first]
+            .iter()
+            .map(|token| Token {
+                kind: token.kind.map_source(|text| reborrow(&token.span, text, 0)),
+                span: token.span,
+            })
+            .collect::<Vec<_>>();
+        let mut errors = Vec::new();
+        let mut old_index = first;
+        let mut old_depth = 0;
+        let mut old_statement = self.statement.clone();
+        let mut resync = None;
+
+        while let Some(result) = self.next() {
+            let token = match result {
+                Ok(token) => token,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+
+            let start = token.span.start.offset;
+            if start >= edit_end {
+                let old_start = (start as isize - delta) as usize;
+                while old_tokens
+                    .get(old_index)
+                    .is_some_and(|old| old.span.start.offset < old_start)
+                {
+                    old_depth += interpolation_depth_change(&old_tokens[old_index].kind);
+                    old_statement.track(&old_tokens[old_index].kind);
+                    old_index += 1;
+                }
+
+                if let Some(old) = old_tokens.get(old_index) {
+                    let old_len = old.span.end.offset - old.span.start.offset;
+                    let len = token.span.end.offset - start;
+                    // Both lexers must also agree on being outside any string
+                    // and on which newlines are significant from here on.
+                    let mut old_after = old_statement.clone();
+                    old_after.track(&old.kind);
+                    if self.interpolations.is_empty()
+                        && old_depth == 0
+                        && old.span.start.offset == old_start
+                        && old_len == len
+                        && old.kind == token.kind
+                        && old_after == self.statement
+                    {
+                        resync = Some((old.span.start, token.span.start));
+                        break;
+                    }
+                }
+            }
+
+            tokens.push(token);
+        }
+
+        let inserted = tokens.len() - first;
+        let removed = match resync {
+            Some((old_start, new_start)) => {
+                let shift = |position: Position| {
+                    let mut position = position;
+                    if position.line == old_start.line {
+                        position.column = position.column + new_start.column - old_start.column;
+                        position.utf16_column =
+                            position.utf16_column + new_start.utf16_column - old_start.utf16_column;
+                    }
+                    position.line = position.line + new_start.line - old_start.line;
+                    position.char = position.char + new_start.char - old_start.char;
+                    position.offset = (position.offset as isize + delta) as usize;
+                    position
+                };
+
+                tokens.extend(old_tokens[old_index..].iter().map(|token| Token {
+                    kind: token.kind.map_source(|text| reborrow(&token.span, text, delta)),
+                    span: Span::new(
+                        token.span.file_id,
+                        shift(token.span.start),
+                        shift(token.span.end),
+                    ),
+                }));
+                old_index - first
+            }
+            None => old_tokens.len() - first,
+        };
+
+        Relexed {
+            tokens,
+            errors,
+            diff: TokenDiff {
+                start: first,
+                removed,
+                inserted,
+            },
+        }
+    }
+
+    /// Lexes the whole input, splitting the result into the tokens that were
+    /// produced and the diagnostics for any input that had to be skipped.
+    pub fn tokenize(self) -> (Vec<Token<'a>>, Vec<LexError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+
+        for result in self {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        (tokens, errors)
     }
 }
 
-impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
+/// How a token changes the number of interpolated strings the lexer is in.
+fn interpolation_depth_change(kind: &TokenType) -> isize {
+    match kind {
+        TokenType::StringHead(_) => 1,
+        TokenType::StringTail(_) => -1,
+        _ => 0,
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn starts_with(&self, pattern: &str) -> bool {
+        self.input[self.position.offset..].starts_with(pattern)
+    }
+
+    /// Whether the cursor (on an `r`) starts a raw string: `r"` or `r#..."`.
+    fn raw_string_ahead(&self) -> bool {
+        let rest = &self.input[self.position.offset + 1..];
+        rest.trim_start_matches('#').starts_with('"')
+    }
+
+    /// Lexes an integer or float literal. Integers may use a `0x`, `0o` or
+    /// `0b` prefix, any literal may separate digits with `_` (`1_000_000`),
+    /// and decimal literals may have a fraction and an exponent (`6.02e23`).
+    fn lex_number(&mut self) -> Result<Token<'a>, LexError> {
+        enum NumberTypes {
+            Int(u32),
+            Float,
+        }
+
+        let start = self.position;
+        let radix = match (self.get_current_char(), self.get_peek_char()) {
+            (Some('0'), Some('x')) | (Some('0'), Some('X')) => 16,
+            (Some('0'), Some('o')) | (Some('0'), Some('O')) => 8,
+            (Some('0'), Some('b')) | (Some('0'), Some('B')) => 2,
+            _ => 10,
+        };
+
+        let mut num_type = NumberTypes::Int(radix);
+        let digits_start = if radix == 10 {
+            self.position.offset
+        } else {
+            self.consume_char();
+            self.consume_char();
+            self.position.offset
+        };
+
+        self.consume_digits(radix);
+        if radix == 10 {
+            if self.get_current_char() == Some('.')
+                && matches!(self.get_peek_char(), Some('0'..='9'))
+            {
+                num_type = NumberTypes::Float;
+                self.consume_char();
+                self.consume_digits(10);
+            }
+
+            if matches!(self.get_current_char(), Some('e') | Some('E')) {
+                let mut exponent = self.input[self.position.offset + 1..].chars();
+                let digit = match exponent.next() {
+                    Some('+') | Some('-') => exponent.next(),
+                    ch => ch,
+                };
+                if matches!(digit, Some('0'..='9')) {
+                    num_type = NumberTypes::Float;
+                    self.consume_char();
+                    if matches!(self.get_current_char(), Some('+') | Some('-')) {
+                        self.consume_char();
+                    }
+                    self.consume_digits(10);
+                }
+            }
+        }
+
+        // Letters or digits running straight on from the literal (`0b102`,
+        // `12abc`) are part of a malformed number, not a new token.
+        let mut malformed = false;
+        while matches!(self.get_current_char(), Some(ch) if ch.is_xid_continue()) {
+            malformed = true;
+            self.consume_char();
+        }
+
+        let span = self.span_from(start);
+        let literal = &self.input[start.offset..span.end.offset];
+        let digits = &self.input[digits_start..span.end.offset];
+        let separators_ok = !digits.is_empty() && !digits.ends_with('_') && !digits.contains("__");
+        let digits = digits.replace('_', "");
+
+        match num_type {
+            NumberTypes::Int(radix) => {
+                if malformed || !separators_ok {
+                    return Err(LexError::new(
+                        LexErrorKind::InvalidInteger(literal.to_string()),
+                        span,
+                    ));
+                }
+
+                match i64::from_str_radix(&digits, radix) {
+                    Ok(n) => Ok(Token {
+                        kind: TokenType::Integer(n),
+                        span,
+                    }),
+                    Err(err) => {
+                        let kind = match err.kind() {
+                            IntErrorKind::PosOverflow => {
+                                LexErrorKind::IntegerOverflow(literal.to_string())
+                            }
+                            _ => LexErrorKind::InvalidInteger(literal.to_string()),
+                        };
+                        Err(LexError::new(kind, span))
+                    }
+                }
+            }
+            NumberTypes::Float => match digits.parse::<f64>() {
+                Ok(n) if !malformed && separators_ok && n.is_finite() => Ok(Token {
+                    kind: TokenType::Float(n),
+                    span,
+                }),
+                _ => Err(LexError::new(
+                    LexErrorKind::InvalidFloat(literal.to_string()),
+                    span,
+                )),
+            },
+        }
+    }
+
+    /// Lexes a prefix `-` and `9223372036854775808` as one literal, since the
+    /// digits alone overflow. Any other number is left for the `-` operator
+    /// to negate, and the cursor is put back on the `-`.
+    fn lex_most_negative_integer(&mut self) -> Option<Token<'a>> {
+        let start = self.position;
+        self.consume_char();
+
+        if let Err(LexError {
+            kind: LexErrorKind::IntegerOverflow(literal),
+            ..
+        }) = self.lex_number()
+        {
+            let lower = literal.to_ascii_lowercase();
+            let (radix, digits) = match lower.get(..2) {
+                Some("0x") => (16, &lower[2..]),
+                Some("0o") => (8, &lower[2..]),
+                Some("0b") => (2, &lower[2..]),
+                _ => (10, &lower[..]),
+            };
+            if u64::from_str_radix(&digits.replace('_', ""), radix) == Ok(1 << 63) {
+                return Some(Token {
+                    kind: TokenType::Integer(i64::MIN),
+                    span: self.span_from(start),
+                });
+            }
+        }
+
+        self.position = start;
+        None
+    }
+
+    /// Consumes digits of the given radix along with `_` separators.
+    fn consume_digits(&mut self, radix: u32) {
+        while matches!(self.get_current_char(), Some(ch) if ch == '_' || ch.is_digit(radix)) {
+            self.consume_char();
+        }
+    }
+
+    /// Lexes a `//` line comment or a `/* */` block comment. Block comments
+    /// nest, so `/* a /* b */ c */` is a single comment.
+    fn lex_comment(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.position;
+        let len = comment_len(&self.input[start.offset..]);
+
+        let end = start.offset + len.unwrap_or(self.input.len() - start.offset);
+        while self.position.offset < end {
+            let ch = self.get_current_char().unwrap();
+            self.consume_string_char(ch);
+        }
+        if len.is_none() {
+            return Err(LexError::new(
+                LexErrorKind::UnterminatedComment,
+                self.span_from(start),
+            ));
+        }
+
+        Ok(Token {
+            kind: TokenType::Comment(&self.input[start.offset..self.position.offset]),
+            span: self.span_from(start),
+        })
+    }
+
+    fn lex_string(&mut self) -> Result<Token<'a>, LexError> {
+        if self.starts_with("\"\"\"") {
+            return self.lex_multiline_string();
+        }
+
+        let start = self.position;
+        self.consume_char();
+        self.lex_string_part(start, start)
+    }
+
+    /// Lexes string contents up to the closing quote or the `{` opening an
+    /// interpolation. `string_start` is where the whole literal began and
+    /// `start` where this part did, at the opening quote or at the `}` that
+    /// closed the previous interpolation.
+    fn lex_string_part(
+        &mut self,
+        string_start: Position,
+        start: Position,
+    ) -> Result<Token<'a>, LexError> {
+        let content_start = self.position.offset;
+
+        // Only allocate once an escape forces the value to differ from the source.
+        let mut owned: Option<String> = None;
+        let mut error: Option<LexError> = None;
+
+        let interpolates = loop {
+            match self.get_current_char() {
+                None => {
+                    return Err(LexError::new(
+                        LexErrorKind::UnterminatedString,
+                        self.span_from(string_start),
+                    ))
+                }
+                Some('"') => break false,
+                Some('{') if self.get_peek_char() != Some('{') => break true,
+                Some(brace @ '{') | Some(brace @ '}') if self.get_peek_char() == Some(brace) => {
+                    let value = owned.get_or_insert_with(|| {
+                        self.input[content_start..self.position.offset].to_string()
+                    });
+                    value.push(brace);
+                    self.consume_char();
+                    self.consume_char();
+                }
+                Some('\\') => {
+                    let value = owned.get_or_insert_with(|| {
+                        self.input[content_start..self.position.offset].to_string()
+                    });
+                    if let Err(err) = self.lex_escape(value) {
+                        error.get_or_insert(err);
+                    }
+                }
+                Some(ch) => {
+                    if let Some(value) = owned.as_mut() {
+                        value.push(ch);
+                    }
+                    self.consume_string_char(ch);
+                }
+            }
+        };
+
+        let content_end = self.position.offset;
+        self.consume_char();
+
+        if interpolates {
+            self.interpolations.push((string_start, 0));
+        }
+        if let Some(err) = error {
+            if self.keep_trivia {
+                self.pending_error = Some(err);
+            } else {
+                // Without its head, the rest of the string would have nothing
+                // opening it, so it is skipped along with the head.
+                if interpolates && start == string_start {
+                    self.skip_interpolations();
+                }
+                return Err(err);
+            }
+        }
+
+        let value = match owned {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(&self.input[content_start..content_end]),
+        };
+        let kind = match (start == string_start, interpolates) {
+            (true, false) => TokenType::String(value),
+            (true, true) => TokenType::StringHead(value),
+            (false, true) => TokenType::StringMiddle(value),
+            (false, false) => TokenType::StringTail(value),
+        };
+        Ok(Token {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Lexes and discards tokens until the interpolation opened last is
+    /// closed, or the input ends.
+    fn skip_interpolations(&mut self) {
+        let depth = self.interpolations.len() - 1;
+        while self.interpolations.len() > depth && self.lex_token().is_some() {}
+    }
+
+    /// Lexes a `"""` string. A newline directly after the opening quotes is
+    /// dropped, and when the closing quotes sit on their own line, that line's
+    /// indentation is stripped from every line of the string.
+    fn lex_multiline_string(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.position;
+        (0..3).for_each(|_| self.consume_char());
+        let content_start = self.position.offset;
+
+        // Each source line of the value, and how many of its leading bytes are
+        // whitespace taken verbatim from the source (escapes never count).
+        let mut lines: Vec<(String, usize)> = vec![(String::new(), 0)];
+        let mut error: Option<LexError> = None;
+
+        loop {
+            let (line, raw_indent) = lines.last_mut().unwrap();
+            match self.get_current_char() {
+                None => {
+                    return Err(LexError::new(
+                        LexErrorKind::UnterminatedString,
+                        self.span_from(start),
+                    ))
+                }
+                Some('"') if self.starts_with("\"\"\"") => break,
+                Some('\\') => {
+                    if let Err(err) = self.lex_escape(line) {
+                        error.get_or_insert(err);
+                    }
+                }
+                Some('\n') => {
+                    self.consume_newline();
+                    lines.push((String::new(), 0));
+                }
+                Some(ch) => {
+                    if (ch == ' ' || ch == '\t') && *raw_indent == line.len() {
+                        *raw_indent += 1;
+                    }
+                    line.push(ch);
+                    self.consume_char();
+                }
+            }
+        }
+
+        let content_end = self.position.offset;
+        (0..3).for_each(|_| self.consume_char());
+        if let Some(err) = error {
+            if !self.keep_trivia {
+                return Err(err);
+            }
+            self.pending_error = Some(err);
+        }
+
+        if lines.len() > 1 && lines[0].0.trim_end_matches('\r').is_empty() {
+            lines.remove(0);
+        }
+
+        let mut indent = 0;
+        if lines.len() > 1 {
+            let (last, raw_indent) = &lines[lines.len() - 1];
+            if *raw_indent == last.len() {
+                indent = *raw_indent;
+                lines.pop();
+            }
+        }
+
+        let value = lines
+            .iter()
+            .map(|(line, raw_indent)| &line[indent.min(*raw_indent)..])
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let source = &self.input[content_start..content_end];
+        let value = if value == source {
+            Cow::Borrowed(source)
+        } else {
+            Cow::Owned(value)
+        };
+        Ok(Token {
+            kind: TokenType::String(value),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Lexes `r"..."` or `r#"..."#`. Nothing is escaped inside a raw string;
+    /// it ends at the first quote followed by as many `#` as opened it.
+    fn lex_raw_string(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.position;
+        self.consume_char();
+
+        let mut hashes = 0;
+        while self.get_current_char() == Some('#') {
+            hashes += 1;
+            self.consume_char();
+        }
+        self.consume_char();
+
+        let content_start = self.position.offset;
+        let terminator = format!("\"{}", "#".repeat(hashes));
+
+        loop {
+            match self.get_current_char() {
+                None => {
+                    return Err(LexError::new(
+                        LexErrorKind::UnterminatedString,
+                        self.span_from(start),
+                    ))
+                }
+                Some('"') if self.starts_with(&terminator) => break,
+                Some(ch) => self.consume_string_char(ch),
+            }
+        }
+
+        let content_end = self.position.offset;
+        (0..terminator.chars().count()).for_each(|_| self.consume_char());
+
+        Ok(Token {
+            kind: TokenType::String(Cow::Borrowed(&self.input[content_start..content_end])),
+            span: self.span_from(start),
+        })
+    }
+
+    fn consume_string_char(&mut self, ch: char) {
+        match ch {
+            '\n' => self.consume_newline(),
+            _ => self.consume_char(),
+        }
+    }
 
-    fn next(&mut self) -> Option<Token<'a>> {
-        let current_char = self.get_current_char();
-        if current_char == None {
-            return None;
+    /// Lexes the escape sequence under the cursor (on a `\\`) into `value`.
+    /// An invalid escape is consumed anyway so the string can still be closed.
+    fn lex_escape(&mut self, value: &mut String) -> Result<(), LexError> {
+        let start = self.position;
+        self.consume_char();
+
+        let escaped = match self.get_current_char() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('u') => return self.lex_unicode_escape(start, value),
+            Some(ch) => {
+                self.consume_string_char(ch);
+                return Err(self.invalid_escape(start));
+            }
+            None => return Err(self.invalid_escape(start)),
+        };
+
+        self.consume_char();
+        value.push(escaped);
+        Ok(())
+    }
+
+    /// Lexes the `u{XXXX}` part of a `\\u{XXXX}` escape, with up to six hex digits.
+    fn lex_unicode_escape(&mut self, start: Position, value: &mut String) -> Result<(), LexError> {
+        self.consume_char();
+        if self.get_current_char() != Some('{') {
+            return Err(self.invalid_escape(start));
+        }
+        self.consume_char();
+
+        let digits_start = self.position.offset;
+        while matches!(self.get_current_char(), Some(ch) if ch.is_ascii_hexdigit()) {
+            self.consume_char();
+        }
+        let digits = &self.input[digits_start..self.position.offset];
+
+        if self.get_current_char() != Some('}') {
+            return Err(self.invalid_escape(start));
         }
+        self.consume_char();
 
+        let code = match digits.len() {
+            1..=6 => u32::from_str_radix(digits, 16).ok(),
+            _ => None,
+        };
+        match code.and_then(char::from_u32) {
+            Some(ch) => {
+                value.push(ch);
+                Ok(())
+            }
+            None => Err(self.invalid_escape(start)),
+        }
+    }
+
+    fn invalid_escape(&self, start: Position) -> LexError {
+        let span = self.span_from(start);
+        LexError::new(
+            LexErrorKind::InvalidEscape(self.input[start.offset..span.end.offset].to_string()),
+            span,
+        )
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn lex_token(&mut self) -> Option<Result<Token<'a>, LexError>> {
+        if let Some(trivia) = self.lex_trivia() {
+            return Some(trivia);
+        }
+
+        let current_char = match self.get_current_char() {
+            Some(ch) => ch,
+            None => {
+                // Input ended inside an interpolation: report the outermost
+                // string once rather than every nested one.
+                let (string_start, _) = *self.interpolations.first()?;
+                self.interpolations.clear();
+                return Some(Err(LexError::new(
+                    LexErrorKind::UnterminatedString,
+                    self.span_from(string_start),
+                )));
+            }
+        };
         let peek_char = self.get_peek_char();
 
-        match current_char.unwrap() {
+        match current_char {
             '(' => self.lex_single_char(TokenType::LParen),
             ')' => self.lex_single_char(TokenType::RParen),
             '[' => self.lex_single_char(TokenType::LBracket),
             ']' => self.lex_single_char(TokenType::RBracket),
-            '{' => self.lex_single_char(TokenType::LBrace),
-            '}' => self.lex_single_char(TokenType::RBrace),
+            '{' => {
+                if let Some((_, depth)) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.lex_single_char(TokenType::LBrace)
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some((_, depth)) if *depth > 0 => {
+                    *depth -= 1;
+                    self.lex_single_char(TokenType::RBrace)
+                }
+                Some(_) => {
+                    let (string_start, _) = self.interpolations.pop().unwrap();
+                    let start = self.position;
+                    self.consume_char();
+                    Some(self.lex_string_part(string_start, start))
+                }
+                None => self.lex_single_char(TokenType::RBrace),
+            },
             ',' => self.lex_single_char(TokenType::Comma),
-            '.' => self.lex_single_char(TokenType::Period),
-            '_' => self.lex_single_char(TokenType::Underscore),
+            '.' => match (peek_char, self.input[self.position.offset..].chars().nth(2)) {
+                (Some('.'), Some('.')) => self.lex_triple_char(TokenType::Ellipsis),
+                (Some('.'), Some('=')) => self.lex_triple_char(TokenType::DotDotEqual),
+                (Some('.'), _) => self.lex_double_char(TokenType::DotDot),
+                _ => self.lex_single_char(TokenType::Period),
+            },
+            '_' if !matches!(peek_char, Some(ch) if ch.is_xid_continue()) => {
+                self.lex_single_char(TokenType::Underscore)
+            }
             '!' => match peek_char {
                 Some('=') => self.lex_double_char(TokenType::BangEqual),
                 _ => self.lex_single_char(TokenType::Bang),
@@ -102,7 +1021,17 @@ impl<'a> Iterator for Lexer<'a> {
             //         String::from(current_char.unwrap()) + &String::from(peek_char.unwrap())[..]
             //     ),
             // },
-            '|' => self.lex_single_char(TokenType::Bar),
+            '|' => match peek_char {
+                Some('|') => self.lex_double_char(TokenType::Or),
+                _ => self.lex_single_char(TokenType::Bar),
+            },
+            '^' => self.lex_single_char(TokenType::Caret),
+            '~' => self.lex_single_char(TokenType::Tilde),
+            ';' => self.lex_single_char(TokenType::Semicolon),
+            '%' => match peek_char {
+                Some('=') => self.lex_double_char(TokenType::PercentEqual),
+                _ => self.lex_single_char(TokenType::Percent),
+            },
 
             '&' => match peek_char {
                 Some('&') => self.lex_double_char(TokenType::And),
@@ -127,6 +1056,12 @@ impl<'a> Iterator for Lexer<'a> {
             '-' => match peek_char {
                 Some('=') => self.lex_double_char(TokenType::MinusEqual),
                 Some('>') => self.lex_double_char(TokenType::Arrow),
+                Some('0'..='9') if !self.statement.after_operand => {
+                    match self.lex_most_negative_integer() {
+                        Some(token) => Some(Ok(token)),
+                        None => self.lex_single_char(TokenType::Minus),
+                    }
+                }
                 _ => {
                     self.lex_single_char(TokenType::Minus)
                 }
@@ -150,6 +1085,7 @@ impl<'a> Iterator for Lexer<'a> {
 
             '*' => match peek_char {
                 Some('=') => self.lex_double_char(TokenType::AsteriskEqual),
+                Some('*') => self.lex_double_char(TokenType::DoubleAsterisk),
                 _ => {
                     self.lex_single_char(TokenType::Asterisk)
                 }
@@ -161,8 +1097,9 @@ impl<'a> Iterator for Lexer<'a> {
             },
             '<' => match peek_char {
                 Some('=') => self.lex_double_char(TokenType::LTEq),
+                Some('<') => self.lex_double_char(TokenType::ShiftLeft),
                 _ => {
-                    self.lex_single_char(TokenType::Asterisk)
+                    self.lex_single_char(TokenType::LT)
                 }
                 // _ => panic!(
                 //     "{} Found illegal token '{}'",
@@ -172,8 +1109,9 @@ impl<'a> Iterator for Lexer<'a> {
             },
             '>' => match peek_char {
                 Some('=') => self.lex_double_char(TokenType::GTEq),
+                Some('>') => self.lex_double_char(TokenType::ShiftRight),
                 _ => {
-                    self.lex_single_char(TokenType::Asterisk)
+                    self.lex_single_char(TokenType::GT)
                 }
                 // _ => panic!(
                 //     "{} Found illegal token '{}'",
@@ -193,128 +1131,103 @@ impl<'a> Iterator for Lexer<'a> {
                 // ),
             },
 
-            '0'..='9' => {
-                enum NumberTypes {
-                    Int,
-                    Float,
-                }
-
-                let position = self.position;
-                let mut num = String::new();
-                let mut num_type = NumberTypes::Int;
-
-                while let Some(ch) = self.get_current_char() {
-                    match ch {
-                        '0'..='9' => {
-                            num.push(ch);
-                            self.consume_char();
-                        }
-                        '.' if matches!(self.get_peek_char(), Some('0'..='9')) => {
-                            num_type = NumberTypes::Float;
-                            num.push(ch);
-                            self.consume_char();
-                        }
-                        _ => break,
-                    }
-                }
-
-                match num_type {
-                    NumberTypes::Int => match num.parse::<usize>() {
-                        Ok(n) => Some(Token {
-                            position,
-                            kind: TokenType::Integer(n),
-                        }),
-                        Err(e) => {
-                            error(format!(
-                                "{} Could not parse '{}' as an integer.",
-                                self.position, num
-                            ));
-                            panic!()
-                        }
-                    },
-                    NumberTypes::Float => match num.parse::<f32>() {
-                        Ok(n) => Some(Token {
-                            position,
-                            kind: TokenType::Float(n),
-                        }),
-                        Err(e) => {
-                            error(format!(
-                                "{} Could not parse '{}' as a float.",
-                                self.position, num
-                            ));
-                            panic!()
-                        }
-                    },
-                }
-            }
-            '"' => {
+            '0'..='9' => Some(self.lex_number()),
+            '"' => Some(self.lex_string()),
+            'r' if self.raw_string_ahead() => Some(self.lex_raw_string()),
+            ch if ch == '_' || ch.is_xid_start() => {
+                let start = self.position;
                 self.consume_char();
-                let position = self.position;
-                let mut end: usize = 0;
-                while let Some(ch) = self.get_current_char() {
-                    match ch {
-                        '"' => {
-                            end = self.position.char;
-                            self.consume_char();
-                            break;
-                        }
-                        '\n' => {
-                            self.position.line += 1;
-                            self.position.column = 0;
-                            self.consume_char();
-                        }
-                        _ => self.consume_char(),
-                    }
-                }
-                return Some(Token {
-                    kind: TokenType::String(&self.input[position.char..end]),
-                    position: self.position,
-                });
-            }
-            'a'..='z' | 'A'..='Z' | '_' => {
-                let position = self.position;
 
                 while let Some(ch) = self.get_current_char() {
-                    match ch {
-                        'A'..='Z' | 'a'..='z' | '_' => {
-                            self.consume_char();
-                        }
-                        _ => break,
+                    if !ch.is_xid_continue() {
+                        break;
                     }
+                    self.consume_char();
                 }
-                let end = self.position.char;
+                let end = self.position.offset;
 
-                let slice = &self.input[position.char..end];
+                let slice = &self.input[start.offset..end];
                 let kind = TokenType::match_keyword(slice);
 
-                Some(Token { kind, position })
-            }
-            '\n' => {
-                self.position.line += 1;
-                self.position.column = 0;
-                self.consume_char();
-                self.next()
+                Some(Ok(Token {
+                    kind,
+                    span: self.span_from(start),
+                }))
             }
-            ' ' | '\t' | '\r' => {
+            ch => {
+                // Skip the offending character so lexing can carry on from the
+                // next one; the caller decides whether the error is fatal.
+                let start = self.position;
                 self.consume_char();
-                self.next()
+                Some(Err(LexError::new(
+                    LexErrorKind::IllegalCharacter(ch),
+                    self.span_from(start),
+                )))
             }
+        }
+    }
+}
 
-            _ => {
-                error(format!(
-                    "{} Found illegal token '{}'",
-                    self.position,
-                    String::from(current_char.unwrap()) + &String::from(peek_char.unwrap())[..]
-                ));
-                panic!()
+/// The length in bytes of the `//` or `/* */` comment `text` starts with,
+/// not counting the newline ending a line comment. Block comments nest, so
+/// `/* a /* b */ c */` is a single comment. `None` if a block comment is
+/// never closed.
+fn comment_len(text: &str) -> Option<usize> {
+    if text.starts_with("//") {
+        return Some(text.find('\n').unwrap_or(text.len()));
+    }
+
+    let mut depth = 0;
+    let mut offset = 0;
+    loop {
+        let rest = &text[offset..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            offset += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            offset += 2;
+            if depth == 0 {
+                return Some(offset);
             }
+        } else {
+            offset += rest.chars().next()?.len_utf8();
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Result<Token<'a>, LexError>> {
+        if let Some(err) = self.pending_error.take() {
+            return Some(Err(err));
+        }
+
+        let result = self.lex_token()?;
+        if let Ok(token) = &result {
+            self.statement.track(&token.kind);
         }
+        Some(result)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::Lexer;
+    use std::borrow::Cow;
+
+    use crate::errors::LexErrorKind;
+    use crate::lexer::{Lexer, TextEdit, TokenDiff};
+    use crate::token::{Span, TokenType};
+
+    fn lex_string(input: &str) -> Cow<'_, str> {
+        let (mut tokens, errors) = Lexer::new(input).tokenize();
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        match tokens.remove(0).kind {
+            TokenType::String(value) => value,
+            kind => panic!("expected a string, got {:?}", kind),
+        }
+    }
     #[test]
     fn it_works() {
         let test_str = r#"1 + 2.3555
@@ -324,4 +1237,584 @@ mod tests {
         let z = l.collect::<Vec<_>>();
         println!("{:#?}", z);
     }
+
+    #[test]
+    fn illegal_characters_are_skipped() {
+        let (tokens, errors) = Lexer::new("a $ b #").tokenize();
+
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![TokenType::Ident("a"), TokenType::Ident("b")]);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, LexErrorKind::IllegalCharacter('$'));
+        assert_eq!(errors[0].span.start.column, 2);
+        assert_eq!(errors[1].kind, LexErrorKind::IllegalCharacter('#'));
+        assert_eq!(errors[1].span.start.column, 6);
+    }
+
+    #[test]
+    fn positions_track_lines_columns_and_offsets() {
+        let (tokens, _) = Lexer::new("ab := 1\n  cd").tokenize();
+
+        let cd = tokens[4].span.start;
+        assert_eq!((cd.line, cd.column, cd.char, cd.offset), (2, 2, 10, 10));
+        assert_eq!(tokens[4].kind, TokenType::Ident("cd"));
+    }
+
+    #[test]
+    fn unicode_identifiers_and_strings() {
+        let (tokens, errors) = Lexer::new("größe := \"héllo 😀\" _π1 😀").tokenize();
+
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Ident("größe"),
+                TokenType::ColonEqual,
+                TokenType::String("héllo 😀".into()),
+                TokenType::Ident("_π1"),
+            ]
+        );
+        assert_eq!(errors[0].kind, LexErrorKind::IllegalCharacter('😀'));
+    }
+
+    #[test]
+    fn columns_are_reported_in_chars_and_utf16_units() {
+        let (tokens, _) = Lexer::new("\"😀\" é x").tokenize();
+
+        let x = tokens[2].span.start;
+        assert_eq!((x.column, x.utf16_column), (6, 7));
+        assert_eq!(x.offset, "\"😀\" é ".len());
+    }
+
+    #[test]
+    fn string_spans_include_quotes() {
+        let (tokens, _) = Lexer::new("x \"hi\"").tokenize();
+
+        let span = tokens[1].span;
+        assert_eq!((span.start.offset, span.end.offset), (2, 6));
+        assert_eq!(tokens[1].kind, TokenType::String("hi".into()));
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(lex_string(r#""a\"b""#), "a\"b");
+        assert_eq!(lex_string(r#""tab\tline\n\\""#), "tab\tline\n\\");
+        assert_eq!(lex_string(r#""\u{1F600}!""#), "😀!");
+        assert!(matches!(lex_string(r#""plain""#), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(lex_string(r#"r"C:\path\n""#), r"C:\path\n");
+        assert_eq!(lex_string(r###"r#"say "hi""#"###), r#"say "hi""#);
+        assert!(matches!(lex_string(r#"r"x""#), Cow::Borrowed("x")));
+    }
+
+    #[test]
+    fn multiline_strings_strip_indentation() {
+        let input = "\"\"\"\n    first\n      second\\t\n    \"\"\"";
+        assert_eq!(lex_string(input), "first\n  second\t");
+
+        assert_eq!(lex_string(r#""""one "quoted" line""""#), r#"one "quoted" line"#);
+    }
+
+    #[test]
+    fn bad_strings_are_reported() {
+        let (tokens, errors) = Lexer::new("\"open\nx").tokenize();
+        assert!(tokens.is_empty());
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedString);
+
+        let (tokens, errors) = Lexer::new(r#""bad \q escape" after"#).tokenize();
+        assert_eq!(errors[0].kind, LexErrorKind::InvalidEscape(r"\q".to_string()));
+        assert_eq!(errors[0].span.start.column, 5);
+        assert_eq!(tokens[0].kind, TokenType::Ident("after"));
+
+        let (tokens, errors) = Lexer::new(r#""\q {a + "{b}"} c" after"#).tokenize();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(tokens[0].kind, TokenType::Ident("after"));
+
+        let (_, errors) = Lexer::new(r#""\u{110000}""#).tokenize();
+        assert_eq!(
+            errors[0].kind,
+            LexErrorKind::InvalidEscape(r"\u{110000}".to_string())
+        );
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        let input = r#""Hello {name}, you are {age + 1}{{!}}""#;
+        let (tokens, errors) = Lexer::new(input).tokenize();
+
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::StringHead("Hello ".into()),
+                TokenType::Ident("name"),
+                TokenType::StringMiddle(", you are ".into()),
+                TokenType::Ident("age"),
+                TokenType::Plus,
+                TokenType::Integer(1),
+                TokenType::StringTail("{!}".into()),
+            ]
+        );
+        assert_eq!(tokens[0].span.source_text(input), "\"Hello {");
+        assert_eq!(tokens[2].span.source_text(input), "}, you are {");
+
+        // Braces inside an interpolation nest, and so do strings.
+        let input = r#""a {f({x: "b {y}"})} c""#;
+        let (tokens, errors) = Lexer::new(input).tokenize();
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::StringHead("a ".into()),
+                TokenType::Ident("f"),
+                TokenType::LParen,
+                TokenType::LBrace,
+                TokenType::Ident("x"),
+                TokenType::Colon,
+                TokenType::StringHead("b ".into()),
+                TokenType::Ident("y"),
+                TokenType::StringTail("".into()),
+                TokenType::RBrace,
+                TokenType::RParen,
+                TokenType::StringTail(" c".into()),
+            ]
+        );
+
+        assert_eq!(lex_string(r#""{{not interpolated}}""#), "{not interpolated}");
+        assert_eq!(lex_string(r#""a } b""#), "a } b");
+
+        let (_, errors) = Lexer::new(r#"x "open {a + "#).tokenize();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedString);
+        assert_eq!(errors[0].span.start.offset, 2);
+    }
+
+    #[test]
+    fn newlines_are_significant_where_statements_can_end() {
+        let kinds = |input| {
+            Lexer::new(input)
+                .map(|result| result.unwrap().kind)
+                .collect::<Vec<_>>()
+        };
+        use TokenType::*;
+
+        assert_eq!(kinds("a\n\n  b\n"), vec![Ident("a"), Newline, Ident("b"), Newline]);
+        assert_eq!(kinds("return\n1 +\n2"), vec![Return, Newline, Integer(1), Plus, Integer(2)]);
+        assert_eq!(
+            kinds("f(a,\n  b\n)\n"),
+            vec![Ident("f"), LParen, Ident("a"), Comma, Ident("b"), RParen, Newline]
+        );
+        assert_eq!(
+            kinds("[{\n  a\n}]"),
+            vec![LBracket, LBrace, Ident("a"), Newline, RBrace, RBracket]
+        );
+        assert_eq!(kinds("a\n  .b // c\n"), vec![Ident("a"), Period, Ident("b"), Newline]);
+        assert_eq!(kinds("a\n// note\n.b"), vec![Ident("a"), Period, Ident("b")]);
+        assert_eq!(
+            kinds("a\n/* x /* y */ */\n  .b"),
+            vec![Ident("a"), Period, Ident("b")]
+        );
+        assert_eq!(kinds("a\n// .b\nc"), vec![Ident("a"), Newline, Ident("c")]);
+        assert_eq!(
+            kinds("\"{a\n}\"\n"),
+            vec![StringHead("".into()), Ident("a"), StringTail("".into()), Newline]
+        );
+    }
+
+    #[test]
+    fn shebang_lines_are_skipped() {
+        let input = "#!/usr/bin/env clay\nx";
+        let (tokens, errors) = Lexer::new(input).tokenize();
+
+        assert!(errors.is_empty());
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, TokenType::Ident("x"));
+        assert_eq!(tokens[0].span.start.line, 2);
+
+        let (tokens, _) = Lexer::new(input).with_comments().tokenize();
+        assert_eq!(tokens[0].kind, TokenType::Comment("#!/usr/bin/env clay"));
+
+        // Only the first line can be a shebang.
+        let (_, errors) = Lexer::new("x\n#!y").tokenize();
+        assert_eq!(errors[0].kind, LexErrorKind::IllegalCharacter('#'));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let input = "a // line comment\n/* block /* nested */ still */ b /**/";
+        let (tokens, errors) = Lexer::new(input).tokenize();
+
+        assert!(errors.is_empty());
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![TokenType::Ident("a"), TokenType::Newline, TokenType::Ident("b")]
+        );
+    }
+
+    #[test]
+    fn comments_can_be_kept() {
+        let input = "a // line\n/* x /* y */ */ b";
+        let (tokens, _) = Lexer::new(input).with_comments().tokenize();
+
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Ident("a"),
+                TokenType::Comment("// line"),
+                TokenType::Newline,
+                TokenType::Comment("/* x /* y */ */"),
+                TokenType::Ident("b"),
+            ]
+        );
+
+        let (_, errors) = Lexer::new("/* open /* */").tokenize();
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn trivia_mode_is_lossless() {
+        let input = "#!clay\nx := |a| -> {\r\n\treturn a   // done\n}\n/* a /* b */ */ \"s\\n{ a }\" r#\"raw\"#  ";
+        let tokens = Lexer::new(input)
+            .with_trivia()
+            .map(|result| result.unwrap())
+            .collect::<Vec<_>>();
+
+        let text = tokens
+            .iter()
+            .map(|token| token.span.source_text(input))
+            .collect::<String>();
+        assert_eq!(text, input);
+
+        assert_eq!(tokens[0].kind, TokenType::Comment("#!clay"));
+        assert_eq!(tokens[3].kind, TokenType::Whitespace(" "));
+        assert!(tokens.iter().any(|t| t.kind == TokenType::Whitespace("\r")));
+        assert_eq!(
+            tokens.iter().filter(|t| t.kind == TokenType::Newline).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn trivia_mode_leaves_gaps_for_errors() {
+        let input = "a $ \"bad \\q escape\" x \"a {b} \\q c\" y \"unterminated";
+        let mut text = String::new();
+        let mut errors = Vec::new();
+
+        for result in Lexer::new(input).with_trivia() {
+            match result {
+                Ok(token) => text.push_str(token.span.source_text(input)),
+                Err(err) => {
+                    // Only errors past the text so far fill a gap; a bad
+                    // escape points inside the string token before it.
+                    if err.span.start.offset >= text.len() {
+                        text.push_str(err.span.source_text(input));
+                    }
+                    errors.push(err.kind);
+                }
+            }
+        }
+
+        assert_eq!(text, input);
+        assert_eq!(
+            errors,
+            vec![
+                LexErrorKind::IllegalCharacter('$'),
+                LexErrorKind::InvalidEscape(r"\q".to_string()),
+                LexErrorKind::InvalidEscape(r"\q".to_string()),
+                LexErrorKind::UnterminatedString
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_literals() {
+        let input = "42 1_000_000 0xFF 0o17 0b1010_1010 3.25 6.02e23 1E-3 2.5e+2 9223372036854775807";
+        let (tokens, errors) = Lexer::new(input).tokenize();
+
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Integer(42),
+                TokenType::Integer(1_000_000),
+                TokenType::Integer(0xFF),
+                TokenType::Integer(0o17),
+                TokenType::Integer(0b1010_1010),
+                TokenType::Float(3.25),
+                TokenType::Float(6.02e23),
+                TokenType::Float(1e-3),
+                TokenType::Float(2.5e2),
+                TokenType::Integer(i64::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_leave_periods_and_identifiers_alone() {
+        let (tokens, _) = Lexer::new("1.len e 2.x").tokenize();
+
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Integer(1),
+                TokenType::Period,
+                TokenType::Ident("len"),
+                TokenType::Ident("e"),
+                TokenType::Integer(2),
+                TokenType::Period,
+                TokenType::Ident("x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn most_negative_integer_is_one_literal() {
+        let (tokens, errors) = Lexer::new("-9223372036854775808").tokenize();
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, TokenType::Integer(i64::MIN));
+        assert_eq!(tokens[0].span.end.column, 20);
+
+        let kinds = |input| {
+            let (tokens, errors) = Lexer::new(input).tokenize();
+            let tokens = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+            let errors = errors.into_iter().map(|e| e.kind).collect::<Vec<_>>();
+            (tokens, errors)
+        };
+        use TokenType::*;
+
+        assert_eq!(
+            kinds("x := -0x8000_0000_0000_0000; return -9223372036854775808"),
+            (
+                vec![
+                    Ident("x"),
+                    ColonEqual,
+                    Integer(i64::MIN),
+                    Semicolon,
+                    Return,
+                    Integer(i64::MIN)
+                ],
+                vec![]
+            )
+        );
+        assert_eq!(kinds("-1"), (vec![Minus, Integer(1)], vec![]));
+
+        // After an operand the '-' is binary, so the digits overflow alone.
+        let overflow = || vec![LexErrorKind::IntegerOverflow("9223372036854775808".to_string())];
+        assert_eq!(kinds("a -9223372036854775808"), (vec![Ident("a"), Minus], overflow()));
+        assert_eq!(kinds("- 9223372036854775808"), (vec![Minus], overflow()));
+        assert_eq!(
+            kinds("-9223372036854775809"),
+            (
+                vec![Minus],
+                vec![LexErrorKind::IntegerOverflow("9223372036854775809".to_string())]
+            )
+        );
+    }
+
+    #[test]
+    fn bad_numbers_are_reported() {
+        let (tokens, errors) =
+            Lexer::new("9223372036854775808 0b102 1__0 7_ 0x 1e999 12abc ok").tokenize();
+
+        let kinds = errors.into_iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                LexErrorKind::IntegerOverflow("9223372036854775808".to_string()),
+                LexErrorKind::InvalidInteger("0b102".to_string()),
+                LexErrorKind::InvalidInteger("1__0".to_string()),
+                LexErrorKind::InvalidInteger("7_".to_string()),
+                LexErrorKind::InvalidInteger("0x".to_string()),
+                LexErrorKind::InvalidFloat("1e999".to_string()),
+                LexErrorKind::InvalidInteger("12abc".to_string()),
+            ]
+        );
+        assert_eq!(tokens[0].kind, TokenType::Ident("ok"));
+    }
+
+    #[test]
+    fn identifiers_with_digits_and_literal_keywords() {
+        let (tokens, _) = Lexer::new("x1 a2b3 true false nil truthy if else elsewhere while for in loop break continue").tokenize();
+
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Ident("x1"),
+                TokenType::Ident("a2b3"),
+                TokenType::True,
+                TokenType::False,
+                TokenType::Nil,
+                TokenType::Ident("truthy"),
+                TokenType::If,
+                TokenType::Else,
+                TokenType::Ident("elsewhere"),
+                TokenType::While,
+                TokenType::For,
+                TokenType::In,
+                TokenType::Loop,
+                TokenType::Break,
+                TokenType::Continue,
+            ]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let input = "< <= << > >= >> % %= * *= ** | || & && ^ ~ ; += -= /= == != ! -> := : . .. ..= ...";
+        let (tokens, errors) = Lexer::new(input).tokenize();
+
+        assert!(errors.is_empty());
+        let kinds = tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::LT,
+                TokenType::LTEq,
+                TokenType::ShiftLeft,
+                TokenType::GT,
+                TokenType::GTEq,
+                TokenType::ShiftRight,
+                TokenType::Percent,
+                TokenType::PercentEqual,
+                TokenType::Asterisk,
+                TokenType::AsteriskEqual,
+                TokenType::DoubleAsterisk,
+                TokenType::Bar,
+                TokenType::Or,
+                TokenType::Ampersand,
+                TokenType::And,
+                TokenType::Caret,
+                TokenType::Tilde,
+                TokenType::Semicolon,
+                TokenType::PlusEqual,
+                TokenType::MinusEqual,
+                TokenType::SlashEqual,
+                TokenType::DoubleEqual,
+                TokenType::BangEqual,
+                TokenType::Bang,
+                TokenType::Arrow,
+                TokenType::ColonEqual,
+                TokenType::Colon,
+                TokenType::Period,
+                TokenType::DotDot,
+                TokenType::DotDotEqual,
+                TokenType::Ellipsis,
+            ]
+        );
+    }
+
+    /// Applies `edit` to `old` and checks that re-lexing incrementally gives
+    /// the same tokens as lexing the edited source from scratch.
+    fn assert_relex_matches(old: &str, range: std::ops::Range<usize>, text: &str) -> TokenDiff {
+        let mut new = old.to_string();
+        new.replace_range(range.clone(), text);
+
+        let (old_tokens, _) = Lexer::new(old).tokenize();
+        let edit = TextEdit { range, text };
+        let relexed = Lexer::new(&new).relex(&old_tokens, &edit);
+
+        let (expected, _) = Lexer::new(&new).tokenize();
+        let simplify = |tokens: &[crate::token::Token]| -> Vec<(String, Span)> {
+            tokens.iter().map(|t| (format!("{:?}", t.kind), t.span)).collect()
+        };
+        assert_eq!(simplify(&relexed.tokens), simplify(&expected), "editing {:?} into {:?}", old, new);
+
+        relexed.diff
+    }
+
+    #[test]
+    fn relex_only_touches_the_edited_region() {
+        let old = "a := 1\nb := foo(a, 2)\nc := \"str\" + b\nd := [1, 2, 3]";
+
+        // `foo` -> `fooz`: the identifier and the token before it are re-lexed.
+        let at = old.find("foo").unwrap() + 3;
+        let diff = assert_relex_matches(old, at..at, "z");
+        assert_eq!(diff, TokenDiff { start: 5, removed: 2, inserted: 2 });
+
+        // `1.` followed by an inserted digit turns into a float.
+        assert_relex_matches("x 1.y", 4..4, "5");
+
+        // Joining two tokens into one.
+        let at = old.find(":=").unwrap();
+        assert_relex_matches(old, at..at + 1, "=");
+
+        // Inserting and removing lines shifts everything after the edit.
+        // The old newline after `1` is dropped: it now follows a blank line.
+        let diff = assert_relex_matches(old, 6..6, "\nx := 10 ** 2\n");
+        assert!(diff.removed <= 3, "{:?}", diff);
+        let at = old.find("b :=").unwrap();
+        assert_relex_matches(old, at - 1..old.find("c :=").unwrap(), "");
+
+        // Columns on the edited line shift, columns on later lines do not.
+        assert_relex_matches(old, 0..1, "longer_name");
+        assert_relex_matches(old, 0..0, "é😀 ");
+
+        // Kept strings are re-borrowed from the new input by their spans.
+        let old = "r#\"x\"# \"\"\"y\"\"\" a \"é{b}c\" r\"d\" // e";
+        let at = old.find(" a ").unwrap() + 1;
+        assert_relex_matches(old, at..at + 1, "aa");
+        assert_relex_matches(old, at..at, "z := ");
+    }
+
+    #[test]
+    fn relex_follows_edits_that_change_later_tokens() {
+        let old = "x := 1 // note\ny := \"a\" + z\nw";
+
+        // Opening a string swallows the rest of the line.
+        let at = old.find("+").unwrap();
+        assert_relex_matches(old, at..at, "\"");
+
+        // Opening a block comment swallows the rest of the file.
+        assert_relex_matches(old, 0..0, "/*");
+
+        // Editing inside a comment.
+        let at = old.find("note").unwrap();
+        assert_relex_matches(old, at..at + 4, "longer note");
+
+        // Edits at the very start and end, and of an empty source.
+        assert_relex_matches(old, 0..0, "q ");
+        assert_relex_matches(old, old.len()..old.len(), "w2");
+        assert_relex_matches("", 0..0, "a b");
+        assert_relex_matches(old, 0..old.len(), "");
+
+        // Edits that change which of the later newlines are significant.
+        assert_relex_matches(old, 0..0, "(");
+        let at = old.find("w").unwrap();
+        assert_relex_matches(old, at..at, ".");
+        assert_relex_matches("f(a\nb\nc)\nd\ne", 1..2, "");
+
+        // Edits before the first token, including ones adding a shebang.
+        assert_relex_matches("  a b", 0..0, "c");
+        assert_relex_matches("\na b", 0..0, "#!/bin/clay");
+    }
+
+    #[test]
+    fn relex_inside_interpolations() {
+        let old = "a := \"x {b + c} y {d}\"\ne := {f}";
+
+        // Edits inside an interpolation, and ones that open or close one.
+        let at = old.find("c}").unwrap();
+        assert_relex_matches(old, at..at + 1, "cc");
+        assert_relex_matches(old, at + 1..at + 2, "");
+        let at = old.find(" y").unwrap();
+        assert_relex_matches(old, at..at, "{");
+        assert_relex_matches(old, at..at, "{{");
+        let at = old.find("\"\n").unwrap();
+        assert_relex_matches(old, at..at + 1, "");
+
+        // A string head with a bad escape still opens an interpolation.
+        assert_relex_matches("\"+\\a{x{ return\na]", 8..10, "");
+    }
 }
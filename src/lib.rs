@@ -1,10 +1,17 @@
 #![allow(dead_code, unreachable_patterns)]
+#![allow(
+    clippy::needless_return,
+    clippy::upper_case_acronyms,
+    clippy::enum_variant_names,
+    clippy::vec_box
+)]
 
-mod ast;
-mod errors;
-mod lexer;
-mod parser;
-mod token;
+pub mod ast;
+pub mod errors;
+pub mod highlight;
+pub mod lexer;
+pub mod parser;
+pub mod token;
 
 #[cfg(test)]
 mod tests {
@@ -1,19 +1,30 @@
 use std::{
     env, fs,
     io::{self, Write},
+    process,
 };
 
+use claylang::{
+    errors::{report, report_stderr},
+    highlight::{self, Format},
+    lexer, parser,
+};
 use colored::Colorize;
-use fs::read_to_string;
 
-mod ast;
-mod errors;
-mod lexer;
-mod parser;
-mod token;
+/// `clay` starts the REPL, `clay highlight <file>` highlights a file, and
+/// anything else runs scripts, as `clay a.cy b.cy -- args...`. A script named
+/// `highlight` can still be run as `clay ./highlight`.
 fn main() -> io::Result<()> {
     let mut args = env::args().collect::<Vec<_>>();
 
+    if args.get(1).map(String::as_str) == Some("highlight") {
+        if let Err(err) = highlight_command(&args[2..]) {
+            report_stderr(err);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
     match args.len() {
         1 => {
             println!(
@@ -32,25 +43,115 @@ fn main() -> io::Result<()> {
                 match input.trim() {
                     "exit" => break,
                     _ => {
-                        let tokens = lexer::Lexer::new(input.trim()).collect::<Vec<_>>();
-                        let stack = parser::Parser::new(tokens).parse_program();
+                        let (tokens, errors) = lexer::Lexer::new(input.trim()).tokenize();
+                        if !errors.is_empty() {
+                            errors.iter().for_each(report);
+                            continue;
+                        }
 
-                        println!("\n{:#?}\n", stack);
+                        let result = parser::Parser::new(tokens).parse_program();
+                        if !result.diagnostics.is_empty() {
+                            result.diagnostics.iter().for_each(report);
+                            continue;
+                        }
+
+                        println!("\n{:#?}\n", result.program);
                     }
                 }
             }
         }
         _ => {
             args.remove(0);
-            for path in args {
-                let content = fs::read_to_string(&path)?;
-                let tokens = lexer::Lexer::new(&content[..]).collect::<Vec<_>>();
-                let stack = parser::Parser::new(tokens).parse_program();
+            let (paths, script_args) = split_script_args(args);
+
+            let mut failed = false;
+            for path in &paths {
+                failed |= !run_script(path, &script_args)?;
+            }
+            if failed {
+                process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
 
-                println!("\n{:#?}\n", stack);
+/// Splits the arguments after `clay` into script paths and the arguments
+/// passed to every script: those after a `--`. The kernel runs a script with
+/// a shebang line as `clay script.cy args...`, so when the first path is such
+/// a script, everything after it is an argument.
+fn split_script_args(mut args: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let shebang = fs::read_to_string(&args[0]).is_ok_and(|content| content.starts_with("#!"));
+    let split = if shebang {
+        1
+    } else {
+        args.iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len())
+    };
+
+    let mut script_args = args.split_off(split);
+    if script_args.first().map(String::as_str) == Some("--") {
+        script_args.remove(0);
+    }
+    (args, script_args)
+}
+
+/// Lexes and parses the script at `path`, reporting any errors. There is no
+/// interpreter yet, so running a script prints what it would run with.
+/// Returns whether the script was free of errors.
+fn run_script(path: &str, args: &[String]) -> io::Result<bool> {
+    let content = fs::read_to_string(path)?;
+    let (tokens, errors) = lexer::Lexer::new(&content[..]).tokenize();
+    if !errors.is_empty() {
+        errors.iter().for_each(report);
+        return Ok(false);
+    }
+
+    let result = parser::Parser::new(tokens).parse_program();
+    if !result.diagnostics.is_empty() {
+        result.diagnostics.iter().for_each(report);
+        return Ok(false);
+    }
+
+    println!("\n{:#?}\nargs: {:?}\n", result.program, args);
+    Ok(true)
+}
+
+/// `clay highlight <file> [--format ansi|html|json]`. Bad arguments are
+/// returned as an `InvalidInput` error holding the usage line.
+fn highlight_command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Usage: clay highlight <file> [--format ansi|html|json]",
+        )
+    };
+    let mut path = None;
+    let mut format = Some(Format::Ansi);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().and_then(|name| Format::from_name(name)),
+            arg if arg.starts_with("--format=") => {
+                format = Format::from_name(&arg["--format=".len()..])
             }
+            arg if path.is_none() => path = Some(arg),
+            _ => return Err(usage()),
         }
     }
 
+    let (path, format) = match (path, format) {
+        (Some(path), Some(format)) => (path, format),
+        _ => return Err(usage()),
+    };
+
+    let content = fs::read_to_string(path)?;
+    let (segments, errors) = highlight::segments(&content);
+    errors.iter().for_each(report_stderr);
+    print!("{}", highlight::render(&segments, format));
+
     Ok(())
 }
@@ -1,66 +1,117 @@
-use core::panic;
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    mem::{self, discriminant, Discriminant},
+};
 
 use crate::{
-    ast::{Expression, MatchPairExpression, Program, Statement},
-    errors::error,
-    token::{self, Token, TokenType},
+    ast::{
+        Expression, InterpolationPart, MapKey, MatchPairExpression, Pattern, Program, Statement,
+    },
+    errors::ParseError,
+    token::{Position, Span, Token, TokenType},
 };
 
-#[derive(Debug, PartialOrd, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
 pub enum Precedence {
     LOWEST = 0,
-    AND = 1,
-    OR = 2,
+    OR = 1,
+    AND = 2,
     EQUALS = 3,
     LESSGREATER = 4,
-    SUM = 5,
-    PRODUCT = 6,
-    PREFIX = 7,
-    CALL = 8,
-    INDEX = 9,
-    MATCH = 10,
+    BITOR = 5,
+    BITXOR = 6,
+    BITAND = 7,
+    SHIFT = 8,
+    SUM = 9,
+    PRODUCT = 10,
+    POWER = 11,
+    PREFIX = 12,
+    CALL = 13,
+    INDEX = 14,
+    MATCH = 15,
 }
 
-pub enum IdentTypes {
-    Destructuring,
-    Normal,
+impl Precedence {
+    /// The binding power of operators at this level. Levels are two apart, so
+    /// that a right-associative operator can bind its right operand one lower.
+    pub fn binding_power(self) -> u8 {
+        self as u8 * 2
+    }
 }
 
-impl Precedence {
-    fn from_tok(token: TokenType) -> Option<Precedence> {
-        match token {
-            TokenType::Equal => Some(Precedence::EQUALS),
-            TokenType::BangEqual => Some(Precedence::EQUALS),
-            TokenType::LT => Some(Precedence::LESSGREATER),
-            TokenType::GT => Some(Precedence::LESSGREATER),
-            TokenType::LTEq => Some(Precedence::LESSGREATER),
-            TokenType::GTEq => Some(Precedence::LESSGREATER),
-            TokenType::Or => Some(Precedence::OR),
-            TokenType::And => Some(Precedence::AND),
-            TokenType::Plus => Some(Precedence::SUM),
-            TokenType::Minus => Some(Precedence::SUM),
-            TokenType::Asterisk => Some(Precedence::PRODUCT),
-            TokenType::Slash => Some(Precedence::PRODUCT),
-            TokenType::LParen => Some(Precedence::CALL),
-            TokenType::LBracket => Some(Precedence::INDEX),
-            TokenType::Match => Some(Precedence::MATCH),
-            _ => Some(Precedence::LOWEST),
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+/// Parses an expression starting at the current token.
+type PrefixParselet<'a> = fn(&mut Parser<'a>) -> Box<Expression<'a>>;
+/// Parses the rest of an expression whose left operand has been parsed, with
+/// the operator as the current token.
+type InfixParselet<'a> = fn(&mut Parser<'a>, Box<Expression<'a>>) -> Box<Expression<'a>>;
+
+#[derive(Clone, Copy)]
+pub struct InfixRule<'a> {
+    pub precedence: Precedence,
+    pub associativity: Associativity,
+    parselet: InfixParselet<'a>,
+}
+
+impl<'a> InfixRule<'a> {
+    /// How tightly the operator binds the operand on its left.
+    pub fn left_binding_power(&self) -> u8 {
+        self.precedence.binding_power()
+    }
+
+    /// The binding power its right operand is parsed with: operators binding
+    /// no tighter than this end the operand. Equal to the left binding power
+    /// for left-associative operators, so `a - b - c` is `(a - b) - c`, and
+    /// one lower for right-associative ones, so `a ** b ** c` is `a ** (b ** c)`.
+    pub fn right_binding_power(&self) -> u8 {
+        match self.associativity {
+            Associativity::Left => self.left_binding_power(),
+            Associativity::Right => self.left_binding_power() - 1,
         }
     }
 }
 
+/// The outcome of parsing: every statement that could be parsed, with
+/// `Statement::Error` placeholders where one could not, and what went wrong.
+#[derive(Debug)]
+pub struct ParseResult<'a> {
+    pub program: Program<'a>,
+    pub diagnostics: Vec<ParseError>,
+}
+
 pub struct Parser<'a> {
     tokens: Vec<Token<'a>>,
     current_position: usize,
+    prefix_parselets: HashMap<Discriminant<TokenType<'a>>, PrefixParselet<'a>>,
+    infix_rules: HashMap<Discriminant<TokenType<'a>>, InfixRule<'a>>,
+    diagnostics: Vec<ParseError>,
+    /// Set after an error until the parser resynchronizes at the end of the
+    /// statement, so that one mistake is reported once.
+    recovering: bool,
+    /// The labels of the loops around the current statement, innermost last,
+    /// with `None` for loops without one.
+    loops: Vec<Option<&'a str>>,
 }
 
-impl<'a, 'b> Parser<'a> {
-    pub fn new(tokens: Vec<Token<'a>>) -> Parser {
-        Parser {
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Token<'a>>) -> Parser<'a> {
+        let mut parser = Parser {
             tokens,
             current_position: 0,
-        }
+            prefix_parselets: HashMap::new(),
+            infix_rules: HashMap::new(),
+            diagnostics: Vec::new(),
+            recovering: false,
+            loops: Vec::new(),
+        };
+        parser.register_parselets();
+        parser
     }
 
     pub fn consume_token(&mut self) {
@@ -68,144 +119,410 @@ impl<'a, 'b> Parser<'a> {
     }
 
     pub fn get_current_token(&self) -> Option<Token<'a>> {
-        match self.tokens.get(self.current_position) {
-            Some(z) => Some(*z),
-            _ => None,
-        }
+        self.tokens.get(self.current_position).cloned()
     }
 
     pub fn get_peek_token(&self) -> Option<Token<'a>> {
-        match self.tokens.get(self.current_position + 1) {
-            Some(z) => Some(*z),
-            _ => None,
+        self.tokens.get(self.current_position + 1).cloned()
+    }
+
+    /// Records an error, unless one was already recorded for this statement.
+    pub fn error(&mut self, span: Span, message: String) {
+        if !self.recovering {
+            self.diagnostics.push(ParseError::new(message, span));
+            self.recovering = true;
+        }
+    }
+
+    /// Records an error that does not stop the statement from parsing, such
+    /// as a duplicate map key. Parsing carries on as normal.
+    pub fn report(&mut self, span: Span, message: String) {
+        if !self.recovering {
+            self.diagnostics.push(ParseError::new(message, span));
+        }
+    }
+
+    /// The span of the current token, or of the last one past the end.
+    pub fn current_span(&self) -> Span {
+        match self
+            .tokens
+            .get(self.current_position)
+            .or(self.tokens.last())
+        {
+            Some(token) => token.span,
+            None => {
+                let start = Position::new(1, 0, 0, 0, 0);
+                Span::new(0, start, start)
+            }
+        }
+    }
+
+    /// A placeholder for an expression that failed to parse at the current
+    /// token.
+    pub fn error_expression(&self) -> Box<Expression<'a>> {
+        Box::new(Expression::Error {
+            span: self.current_span(),
+        })
+    }
+
+    /// Parses an expression starting at the current token, reporting an
+    /// error and returning a placeholder if there is none.
+    pub fn expect_expression(
+        &mut self,
+        precedence: Precedence,
+        reason: &str,
+    ) -> Box<Expression<'a>> {
+        return self.expect_expression_bp(precedence.binding_power(), reason);
+    }
+
+    fn expect_expression_bp(&mut self, min_binding_power: u8, reason: &str) -> Box<Expression<'a>> {
+        match self.parse_expression_bp(min_binding_power) {
+            Some(expression) => expression,
+            None => {
+                self.error(
+                    self.current_span(),
+                    format!(
+                        "Expected an expression {}, received {} instead.",
+                        reason,
+                        describe(self.get_current_token().as_ref())
+                    ),
+                );
+                self.error_expression()
+            }
         }
     }
 
     pub fn expect_peek(&mut self, kind: TokenType, reason: &str) -> bool {
-        let current_tok = self.get_current_token().unwrap();
-        if let Some(peek) = self.get_peek_token() {
-            if peek.kind == kind {
+        match self.get_peek_token() {
+            Some(peek) if peek.kind == kind => {
                 self.consume_token();
                 return true;
-            } else {
-                error(format!("{} Expected token of type '{:?}' to be after token of type '{:?}' when {}, received '{:?}' instead.", peek.position, kind, current_tok.kind, reason, peek.kind));
+            }
+            peek => {
+                let span = peek.as_ref().map_or(self.current_span(), |peek| peek.span);
+                self.error(
+                    span,
+                    format!(
+                        "Expected '{:?}' when {}, received {} instead.",
+                        kind,
+                        reason,
+                        describe(peek.as_ref())
+                    ),
+                );
                 return false;
             }
-        } else {
-            error(format!(
-                "{} Out of index for peek token.",
-                current_tok.position
-            ));
-            return false;
         }
     }
 
     pub fn peek_is(&mut self, kind: TokenType) -> bool {
-        if let Some(peek) = self.get_peek_token() {
-            return kind == peek.kind;
-        } else {
-            error(format!("Out of index for peek token."));
-            return false;
-        }
+        return self.peek_is_kind(&kind);
     }
 
-    pub fn get_current_precedence(&mut self) -> Option<Precedence> {
-        let current_tok = self.get_current_token().unwrap();
+    /// Consumes any newlines after the current token, returning whether
+    /// there were some.
+    pub fn skip_peek_newlines(&mut self) -> bool {
+        let mut skipped = false;
+        while self.peek_is_kind(&TokenType::Newline) {
+            self.consume_token();
+            skipped = true;
+        }
+        return skipped;
+    }
 
-        return Precedence::from_tok(current_tok.kind);
+    fn peek_is_kind(&self, kind: &TokenType) -> bool {
+        return self.get_peek_token().is_some_and(|peek| peek.kind == *kind);
     }
 
-    pub fn get_peek_precedence(&mut self) -> Option<Precedence> {
-        let peek_token = self.get_peek_token();
+    /// Reports an error unless the current token ends a statement: it must be
+    /// followed by a newline, a `;`, the end of input, or `closing`, the token
+    /// closing the enclosing block.
+    pub fn expect_statement_end(&mut self, closing: Option<&TokenType<'a>>) {
+        if let Some(peek) = self.get_peek_token() {
+            let ends = match peek.kind {
+                TokenType::Newline | TokenType::Semicolon => true,
+                ref kind => Some(kind) == closing,
+            };
+            if !ends {
+                self.error(
+                    peek.span,
+                    format!(
+                        "Expected a newline or ';' to end the statement, received '{:?}' instead.",
+                        peek.kind
+                    ),
+                );
+            }
+        }
+    }
 
-        if let Some(tok) = peek_token {
-            return Precedence::from_tok(tok.kind);
+    /// Skips the rest of a statement that failed to parse, given the index of
+    /// its first token. Stops at the first newline or `;` at or after the
+    /// current token that is not nested in brackets, at `closing`, or at the
+    /// end of input.
+    fn synchronize(&mut self, start: usize, closing: Option<&TokenType<'a>>) {
+        let mut depth = 0;
+        let mut index = start;
+        while let Some(token) = self.tokens.get(index) {
+            let reached = index >= self.current_position;
+            match token.kind {
+                TokenType::Newline | TokenType::Semicolon if depth == 0 && reached => break,
+                TokenType::LParen | TokenType::LBracket | TokenType::LBrace => depth += 1,
+                TokenType::RParen | TokenType::RBracket | TokenType::RBrace => {
+                    if depth > 0 {
+                        depth -= 1;
+                    } else if reached && Some(&token.kind) == closing {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            index += 1;
         }
 
-        return None;
+        self.current_position = index;
+        self.recovering = false;
     }
 
-    pub fn parse_program(&mut self) -> Program<'a> {
-        let mut statements: Vec<Statement> = Vec::new();
-        let current_pos = self.current_position;
-        let length = self.tokens.len();
+    pub fn parse_program(mut self) -> ParseResult<'a> {
+        let statements = self.parse_statements(None);
 
-        while current_pos != length {
-            let parsed_statement = self.parse_statement();
+        return ParseResult {
+            program: Program { statements },
+            diagnostics: self.diagnostics,
+        };
+    }
 
-            if let Some(stmt) = parsed_statement {
-                statements.push(stmt);
-                self.consume_token();
-            } else {
+    /// Parses statements up to `closing` (left as the current token) or the
+    /// end of input, resynchronizing at the end of any statement with an
+    /// error.
+    fn parse_statements(&mut self, closing: Option<&TokenType<'a>>) -> Vec<Statement<'a>> {
+        let mut statements: Vec<Statement> = Vec::new();
+        while let Some(token) = self.get_current_token() {
+            if Some(&token.kind) == closing {
                 break;
             }
+            if matches!(token.kind, TokenType::Semicolon | TokenType::Newline) {
+                self.consume_token();
+                continue;
+            }
+
+            let start = self.current_position;
+            let mut statement = self.parse_statement();
+            if !self.recovering {
+                self.expect_statement_end(closing);
+            }
+
+            // A statement with an error is kept as far as it got, with
+            // placeholders where parsing failed; the rest of it is skipped.
+            if self.recovering {
+                self.synchronize(start, closing);
+                if let Statement::Error { span } = &mut statement {
+                    let end = &self.tokens[(self.current_position - 1).max(start)];
+                    *span = span.to(end.span);
+                }
+                statements.push(statement);
+                continue;
+            }
+
+            statements.push(statement);
+            self.consume_token();
+        }
+
+        return statements;
+    }
+}
+
+/// How a token is named in diagnostics.
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(token) => format!("'{:?}'", token.kind),
+        None => "the end of input".to_string(),
+    }
+}
+
+/// Combines alternatives into an or-pattern, flattening nested ones.
+fn or_pattern(patterns: Vec<Pattern>) -> Pattern {
+    let mut alternatives = Vec::new();
+    for pattern in patterns {
+        match pattern {
+            Pattern::Or {
+                alternatives: nested,
+                ..
+            } => alternatives.extend(nested),
+            pattern => alternatives.push(pattern),
         }
+    }
+
+    let span = alternatives[0]
+        .span()
+        .to(alternatives[alternatives.len() - 1].span());
+    return Pattern::Or { alternatives, span };
+}
+
+/// Whether a pattern matches every value, making any later arm unreachable.
+fn matches_everything(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Wildcard(_) | Pattern::Binding(_) => true,
+        Pattern::Or { alternatives, .. } => alternatives.iter().any(matches_everything),
+        _ => false,
+    }
+}
 
-        return Program { statements };
+/// Adds the names a pattern binds to `names`. An or-pattern binds the names
+/// of its first alternative, the others having been checked against it.
+fn bound_names<'a>(pattern: &Pattern<'a>, names: &mut Vec<&'a str>) {
+    match pattern {
+        Pattern::Binding(Token {
+            kind: TokenType::Ident(name),
+            ..
+        })
+        | Pattern::Rest {
+            name:
+                Some(Token {
+                    kind: TokenType::Ident(name),
+                    ..
+                }),
+            ..
+        } => names.push(name),
+        Pattern::Array { elements, .. } => elements
+            .iter()
+            .for_each(|element| bound_names(element, names)),
+        Pattern::Map { fields, .. } => fields
+            .iter()
+            .for_each(|(_, pattern)| bound_names(pattern, names)),
+        Pattern::Or { alternatives, .. } => bound_names(&alternatives[0], names),
+        _ => {}
     }
 }
 
+/// Whether an expression can be the target of an assignment.
+fn is_assignable(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::NormalIdentifier { .. }
+            | Expression::MemberAccess { .. }
+            | Expression::IndexExpression { .. }
+    )
+}
+
+/// The span covering a run of tokens, such as the names in `a.b.c` or `a, b`.
+fn span_of_tokens(tokens: &[Token]) -> Span {
+    let first = tokens.first().expect("span of an empty token list");
+    first.span.to(tokens[tokens.len() - 1].span)
+}
+
 impl<'a> Parser<'a> {
     pub fn parse_integer_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
         Box::new(Expression::IntegerLiteral {
-            token: self.get_current_token().unwrap(),
+            span: token.span,
+            token,
         })
     }
 
     pub fn parse_float_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
         Box::new(Expression::FloatLiteral {
-            token: self.get_current_token().unwrap(),
+            span: token.span,
+            token,
         })
     }
 
     pub fn parse_string_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
         Box::new(Expression::StringLiteral {
-            token: self.get_current_token().unwrap(),
+            span: token.span,
+            token,
         })
     }
 
-    pub fn parse_underscore_literal(&self) -> Box<Expression<'a>> {
-        Box::new(Expression::UnderscoreLiteral {
-            token: self.get_current_token().unwrap(),
-        })
-    }
+    /// Parses `"a {x} b"`, starting on its `StringHead`. Each interpolation is
+    /// a full expression, followed by the `StringMiddle` or `StringTail` that
+    /// continues the string.
+    pub fn parse_interpolated_string(&mut self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        let mut parts = Vec::new();
+        let mut current = token.clone();
+
+        loop {
+            let (text, done) = match &current.kind {
+                TokenType::StringHead(text) | TokenType::StringMiddle(text) => (text, false),
+                TokenType::StringTail(text) => (text, true),
+                _ => unreachable!(),
+            };
+            if !text.is_empty() {
+                parts.push(InterpolationPart::Literal(text.clone()));
+            }
+            if done {
+                break;
+            }
 
-    pub fn parse_ident_literal(&mut self, already: Option<Vec<Token<'a>>>) -> Box<Expression<'a>> {
-        let mut idents: Vec<Token<'a>> = Vec::new();
-        if let Some(z) = already {
-            // self.consume_token();
-            return Box::new(Expression::NormalIdentifier { idents: z });
-        }
-        idents.push(self.get_current_token().unwrap());
+            self.consume_token();
+            let expression = self.expect_expression(Precedence::LOWEST, "inside '{}'");
+            parts.push(InterpolationPart::Expression(expression));
 
-        while let Some(peek) = self.get_peek_token() {
-            match peek.kind {
-                TokenType::Period => {
-                    self.consume_token();
-                    if let Some(peek) = self.get_peek_token() {
-                        match peek.kind {
-                            TokenType::Ident(_) => {
-                                self.consume_token();
-                                idents.push(self.get_current_token().unwrap());
-                            }
-                            _ => error(format!("Expected Ident after period.")),
-                        }
-                    } else {
-                        error(format!("Expected Ident after period."));
-                    }
-                }
-                _ => {
-                    break;
+            self.consume_token();
+            current = match self.get_current_token() {
+                Some(
+                    next @ Token {
+                        kind: TokenType::StringMiddle(_) | TokenType::StringTail(_),
+                        ..
+                    },
+                ) => next,
+                next => {
+                    self.error(
+                        self.current_span(),
+                        format!(
+                            "Expected '}}' to close the string interpolation, received {} instead.",
+                            describe(next.as_ref())
+                        ),
+                    );
+                    return self.error_expression();
                 }
-            }
+            };
         }
 
-        return Box::new(Expression::NormalIdentifier { idents });
+        return Box::new(Expression::InterpolatedString {
+            span: token.span.to(current.span),
+            token,
+            parts,
+        });
+    }
+
+    pub fn parse_boolean_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        Box::new(Expression::BooleanLiteral {
+            span: token.span,
+            token,
+        })
+    }
+
+    pub fn parse_nil_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        Box::new(Expression::NilLiteral {
+            span: token.span,
+            token,
+        })
+    }
+
+    pub fn parse_underscore_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        Box::new(Expression::UnderscoreLiteral {
+            span: token.span,
+            token,
+        })
     }
 
-    pub fn parse_ident_literals(&mut self) -> (Vec<Token<'a>>, IdentTypes) {
-        let mut kind = IdentTypes::Destructuring;
+    pub fn parse_ident_literal(&self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        Box::new(Expression::NormalIdentifier {
+            span: token.span,
+            token,
+        })
+    }
 
+    /// Parses a comma separated list of names, such as function parameters,
+    /// leaving the current token on the last one.
+    pub fn parse_ident_literals(&mut self) -> Vec<Token<'a>> {
         let mut idents: Vec<Token<'a>> = Vec::new();
         if let Some(t) = self.get_current_token() {
             if let TokenType::Ident(_) = t.kind {
@@ -213,70 +530,85 @@ impl<'a> Parser<'a> {
             }
         }
 
-        while let Some(peek) = self.get_peek_token() {
-            match peek.kind {
-                TokenType::Period => {
-                    // idents.push(self.get_current_token().unwrap());
-                    kind = IdentTypes::Normal;
-                    self.consume_token();
-                    if let Some(peek) = self.get_peek_token() {
-                        match peek.kind {
-                            TokenType::Ident(_) => {
-                                self.consume_token();
-                                idents.push(self.get_current_token().unwrap());
-                            }
-                            _ => error(format!("Expected IDEnt after period.")),
-                        }
-                    } else {
-                        error(format!("Expected IDEnt after period."));
-                    }
-                }
-                TokenType::Comma => {
-                    // idents.push(self.get_current_token().unwrap());
-                    kind = IdentTypes::Destructuring;
-                    self.consume_token();
-                    if let Some(peek) = self.get_peek_token() {
-                        match peek.kind {
-                            TokenType::Ident(_) => {
-                                self.consume_token();
-                                idents.push(self.get_current_token().unwrap());
-                            }
-                            _ => error(format!("Expected IDEnt after comma.")),
-                        }
-                    } else {
-                        error(format!("Expected IDEnt after comma."));
-                    }
-                }
-                _ => {
-                    self.consume_token();
-
-                    return (idents, kind);
-                }
+        while self.peek_is_kind(&TokenType::Comma) {
+            self.consume_token();
+            if !self.expect_ident_after("','") {
+                break;
             }
+            idents.push(self.get_current_token().unwrap());
         }
 
-        (idents, kind)
+        idents
+    }
+
+    /// Moves onto the identifier after `separator`, reporting an error if the
+    /// next token is not one.
+    fn expect_ident_after(&mut self, separator: &str) -> bool {
+        match self.get_peek_token() {
+            Some(Token {
+                kind: TokenType::Ident(_),
+                ..
+            }) => {
+                self.consume_token();
+                return true;
+            }
+            peek => {
+                let span = peek.as_ref().map_or(self.current_span(), |peek| peek.span);
+                self.error(
+                    span,
+                    format!(
+                        "Expected an identifier after {}, received {} instead.",
+                        separator,
+                        describe(peek.as_ref())
+                    ),
+                );
+                return false;
+            }
+        }
     }
 
     pub fn parse_function_expression(&mut self) -> Box<Expression<'a>> {
         let token = self.get_current_token().unwrap();
         self.consume_token();
-        let (idents, _) = self.parse_ident_literals();
-
-        if idents.len() != 0 && self.get_current_token().unwrap().kind != TokenType::Bar {
-            error(format!("{} Parameter declarations in a function definition must be followed by a '|', received {:?} instead.", self.get_current_token().unwrap().position, self.get_current_token().unwrap().kind))
-        }
-
-        if self.get_current_token().unwrap().kind == TokenType::Bar {
+        let idents = self.parse_ident_literals();
+        if !idents.is_empty() {
             self.consume_token();
         }
 
-        if let Some(tok) = self.get_current_token() {
-            if tok.kind != TokenType::Arrow {
-                error(format!("{} Expected '->' to follow function parameter declarations, received {:?} instead. Make sure your bar pair is followed by an arrow.", tok.position, tok.kind));
+        match self.get_current_token() {
+            Some(Token {
+                kind: TokenType::Bar,
+                ..
+            }) => {}
+            current => {
+                self.error(
+                    self.current_span(),
+                    format!(
+                        "Parameter declarations in a function definition must be followed by a '|', received {} instead.",
+                        describe(current.as_ref())
+                    ),
+                );
+                return self.error_expression();
+            }
+        }
+        let parameters_span = token.span.to(self.current_span());
+
+        match self.get_peek_token() {
+            Some(Token {
+                kind: TokenType::Arrow,
+                ..
+            }) => self.consume_token(),
+            Some(peek) => {
+                self.error(peek.span, format!("Expected '->' to follow function parameter declarations, received {} instead. Make sure your bar pair is followed by an arrow.", describe(Some(&peek))));
+                return self.error_expression();
+            }
+            None => {
+                self.error(
+                    self.current_span(),
+                    "A function needs to have a body, but I couldn't find any after the declaration.".to_string(),
+                );
+                return self.error_expression();
             }
-        } else {
-            error(format!("{} A function needs to have a body, but I couldn't find any after the declaration.", self.tokens[self.current_position-1].position));
         }
 
         self.consume_token();
@@ -284,39 +616,52 @@ impl<'a> Parser<'a> {
         let current_tok = self.get_current_token();
         if let Some(tok) = current_tok {
             match tok.kind {
-                TokenType::LBrace => {
+                TokenType::LBrace if !self.brace_starts_map(self.current_position) => {
                     let statements = self.parse_block_statement(TokenType::RBrace);
-                    // self.consume_token();
                     return Box::new(Expression::FunctionLiteral {
+                        span: token.span.to(statements.span()),
                         token,
-                        parameters: Box::new(Expression::DefinitionIdentifier { idents }),
-                        statements,
+                        parameters: Box::new(Expression::DefinitionIdentifier {
+                            span: parameters_span,
+                            idents,
+                        }),
+                        statements: Box::new(statements),
                     });
                 }
                 _ => {
-                    let expr = self.parse_expression(Precedence::LOWEST, None).unwrap_or_else(|| {error(format!("{} When '{{' is not provided in the function body, Clay expects a single expression.", tok.position));
-                panic!()});
+                    let expr = self.expect_expression(
+                        Precedence::LOWEST,
+                        "as the function body when '{' is not provided",
+                    );
+                    let body_span = expr.span();
                     let stmt = Statement::ReturnStatement {
-                        token: tok,
-                        value: expr,
+                        token: tok.clone(),
+                        value: Some(expr),
+                        span: body_span,
                     };
-                    let mut stmts: Vec<Statement<'a>> = Vec::new();
-                    stmts.push(stmt);
+                    let stmts: Vec<Statement<'a>> = vec![stmt];
 
-                    self.consume_token();
                     return Box::new(Expression::FunctionLiteral {
+                        span: token.span.to(body_span),
                         token,
-                        parameters: Box::new(Expression::DefinitionIdentifier { idents }),
-                        statements: Statement::BlockStatement {
+                        parameters: Box::new(Expression::DefinitionIdentifier {
+                            span: parameters_span,
+                            idents,
+                        }),
+                        statements: Box::new(Statement::BlockStatement {
                             token: tok,
                             statements: stmts,
-                        },
+                            span: body_span,
+                        }),
                     });
                 }
             }
         } else {
-            error(format!("{} Expected to see a function body after parameter declarations, but received nothing.", self.tokens[self.current_position-1]));
-            panic!();
+            self.error(
+                self.current_span(),
+                "Expected to see a function body after parameter declarations, but received nothing.".to_string(),
+            );
+            return self.error_expression();
         }
     }
 
@@ -325,6 +670,7 @@ impl<'a> Parser<'a> {
         let expressions = self.parse_expression_list(TokenType::RBracket);
 
         return Box::new(Expression::ArrayLiteral {
+            span: token.span.to(self.current_span()),
             token,
             elements: expressions,
         });
@@ -332,304 +678,698 @@ impl<'a> Parser<'a> {
 }
 
 impl<'a> Parser<'a> {
-    pub fn parse_statement(&mut self) -> Option<Statement<'a>> {
-        let token = self.get_current_token();
-        if let Some(to) = token {
-            return match to.kind {
-                TokenType::Import => self.parse_import_statement(),
-                TokenType::Ident(_) => self.parse_identifier_statement(),
-                TokenType::Return => self.parse_return_statement(),
-                _ => self.parse_expression_statement(None),
-            };
-        } else {
-            return None;
-        }
+    pub fn parse_statement(&mut self) -> Statement<'a> {
+        let token = self.get_current_token().unwrap();
+        return match token.kind {
+            TokenType::Import => self.parse_import_statement(),
+            TokenType::Ident(_) if self.starts_label() => self.parse_labelled_loop(),
+            TokenType::Ident(_) => self.parse_identifier_statement(),
+            TokenType::While | TokenType::For | TokenType::Loop => self.parse_loop_statement(None),
+            TokenType::Break => self.parse_break_statement(),
+            TokenType::Continue => self.parse_continue_statement(),
+            TokenType::Return => self.parse_return_statement(),
+            TokenType::Else => {
+                self.error(
+                    token.span,
+                    "Found an 'else' without an 'if' before it.".to_string(),
+                );
+                Statement::Error { span: token.span }
+            }
+            _ => self.parse_expression_statement(),
+        };
     }
 
-    pub fn parse_import_statement(&mut self) -> Option<Statement<'a>> {
+    pub fn parse_import_statement(&mut self) -> Statement<'a> {
         let token = self.get_current_token().unwrap();
 
-        self.consume_token();
-        let ident = self.get_current_token().unwrap();
-        Some(Statement::ImportStatement {
-            token,
-            value: ident,
-        })
+        match self.get_peek_token() {
+            Some(
+                ident @ Token {
+                    kind: TokenType::Ident(_),
+                    ..
+                },
+            ) => {
+                self.consume_token();
+                Statement::ImportStatement {
+                    span: token.span.to(ident.span),
+                    token,
+                    value: ident,
+                }
+            }
+            peek => {
+                let span = peek.as_ref().map_or(token.span, |peek| peek.span);
+                self.error(
+                    span,
+                    format!(
+                        "Expected a module name after 'import', received {} instead.",
+                        describe(peek.as_ref())
+                    ),
+                );
+                Statement::Error { span: token.span }
+            }
+        }
     }
 
-    pub fn parse_expression_statement(
-        &mut self,
-        idents: Option<Vec<Token<'a>>>,
-    ) -> Option<Statement<'a>> {
+    pub fn parse_expression_statement(&mut self) -> Statement<'a> {
         let token = self.get_current_token().unwrap();
-        let expression = self.parse_expression(Precedence::LOWEST, idents);
-        if let Some(exp) = expression {
-            return Some(Statement::ExpressionStatement {
-                token,
-                expression: exp,
-            });
+        match self.parse_expression(Precedence::LOWEST) {
+            Some(exp) => self.parse_assignment(token, exp),
+            None => {
+                self.error(
+                    token.span,
+                    format!(
+                        "Expected a statement, received {} instead.",
+                        describe(Some(&token))
+                    ),
+                );
+                Statement::Error { span: token.span }
+            }
         }
-
-        return None;
     }
 
-    pub fn parse_identifier_statement(&mut self) -> Option<Statement<'a>> {
-        let mut kind = IdentTypes::Destructuring;
-        let (idents, _) = self.parse_ident_literals();
-        if let Some(token) = self.get_current_token() {
-            match token.kind {
-                TokenType::Ident(_) => {}
-                _ => self.current_position -= 1,
+    /// Turns `target = value` and compound assignments such as
+    /// `target += value` into an `UpdateStatement`, and any other expression
+    /// into an expression statement.
+    fn parse_assignment(
+        &mut self,
+        token: Token<'a>,
+        expression: Box<Expression<'a>>,
+    ) -> Statement<'a> {
+        let operator = match self.get_peek_token().map(|peek| peek.kind) {
+            Some(TokenType::Equal) => None,
+            Some(kind) if kind.compound_operator().is_some() => kind.compound_operator(),
+            _ => {
+                return Statement::ExpressionStatement {
+                    token,
+                    span: expression.span(),
+                    expression,
+                }
             }
+        };
+
+        if !is_assignable(&expression) {
+            self.error(
+                expression.span(),
+                "Only a name, a member or an index can be assigned to.".to_string(),
+            );
+            return Statement::Error {
+                span: expression.span(),
+            };
         }
+        self.consume_token();
+        let assignment = self.get_current_token().unwrap();
+        self.consume_token();
+        let value = self.expect_expression(Precedence::LOWEST, "after the assignment operator");
+        return Statement::UpdateStatement {
+            span: expression.span().to(value.span()),
+            token: assignment,
+            operator,
+            ident: expression,
+            expression: value,
+        };
+    }
 
-        if let Some(token) = self.get_peek_token() {
-            match self.get_peek_token().unwrap().kind {
-                TokenType::ColonEqual => {
-                    kind = IdentTypes::Destructuring;
-                }
-                TokenType::Equal => {
-                    kind = IdentTypes::Normal;
-                }
-                _ => {
-                    let exp = self.parse_expression_statement(Some(idents));
+    /// Parses a statement starting with a name: a definition such as
+    /// `a, b := value`, or else an expression statement or assignment.
+    pub fn parse_identifier_statement(&mut self) -> Statement<'a> {
+        if !self.starts_definition() {
+            return self.parse_expression_statement();
+        }
 
-                    return exp;
-                }
-            }
-            self.consume_token();
+        let idents = self.parse_ident_literals();
+        self.consume_token();
+        let token = self.get_current_token().unwrap();
 
-            let token = self.get_current_token().unwrap();
+        self.consume_token();
+        let expression =
+            self.expect_expression(Precedence::LOWEST, "after the assignment operator");
 
-            self.consume_token();
-            let expr = self.parse_expression(Precedence::LOWEST, None);
-
-            if let Some(expression) = expr {
-                match kind {
-                    IdentTypes::Destructuring => {
-                        return Some(Statement::AssignStatement {
-                            token,
-                            expression,
-                            defined: Box::new(Expression::DefinitionIdentifier { idents }),
-                        })
-                    }
-                    IdentTypes::Normal => {
-                        return Some(Statement::UpdateStatement {
-                            token,
-                            expression,
-                            ident: Box::new(Expression::NormalIdentifier { idents }),
-                        })
-                    }
-                }
-            } else {
-                error(format!("Expected expression after assignment operator."));
-                panic!();
-            }
-        } else {
-            let exp = self.parse_expression_statement(Some(idents));
+        let idents_span = span_of_tokens(&idents);
+        Statement::AssignStatement {
+            span: idents_span.to(expression.span()),
+            token,
+            expression,
+            defined: Box::new(Expression::DefinitionIdentifier {
+                idents,
+                span: idents_span,
+            }),
+        }
+    }
 
-            return exp;
+    /// Whether the tokens from the current one on are names separated by
+    /// commas and followed by `:=`.
+    fn starts_definition(&self) -> bool {
+        let mut position = self.current_position;
+        loop {
+            match self.tokens.get(position).map(|token| &token.kind) {
+                Some(TokenType::Ident(_)) => {}
+                _ => return false,
+            }
+            match self.tokens.get(position + 1).map(|token| &token.kind) {
+                Some(TokenType::Comma) => position += 2,
+                Some(TokenType::ColonEqual) => return true,
+                _ => return false,
+            }
         }
     }
 
-    pub fn parse_return_statement(&mut self) -> Option<Statement<'a>> {
-        let token = self.get_current_token().unwrap();
-        self.consume_token();
+    /// Whether the current token labels a loop, as in `outer: while`.
+    fn starts_label(&self) -> bool {
+        let kind = |offset| {
+            self.tokens
+                .get(self.current_position + offset)
+                .map(|token: &Token| &token.kind)
+        };
+        return kind(1) == Some(&TokenType::Colon)
+            && matches!(
+                kind(2),
+                Some(TokenType::While) | Some(TokenType::For) | Some(TokenType::Loop)
+            );
+    }
 
-        let value = self.parse_expression(Precedence::LOWEST, None).unwrap();
-        return Some(Statement::ReturnStatement { token, value });
+    fn parse_labelled_loop(&mut self) -> Statement<'a> {
+        let label = self.get_current_token().unwrap();
+        self.consume_token();
+        self.consume_token();
+        return self.parse_loop_statement(Some(label));
     }
 
-    pub fn parse_block_statement(&mut self, end_type: TokenType<'a>) -> Statement<'a> {
+    /// Parses a `while`, `for` or `loop` statement starting at the current
+    /// token.
+    pub fn parse_loop_statement(&mut self, label: Option<Token<'a>>) -> Statement<'a> {
         let token = self.get_current_token().unwrap();
+        let start = label.as_ref().map_or(token.span, |label| label.span);
+        let name = match label.as_ref().map(|label| &label.kind) {
+            Some(TokenType::Ident(name)) => Some(*name),
+            _ => None,
+        };
 
-        self.consume_token();
-        let mut statements: Vec<Statement<'a>> = Vec::new();
-        while (self.get_current_token().unwrap().kind != end_type)
-            && self.current_position != self.tokens.len()
-        {
-            let statement = self.parse_statement();
-            match statement {
-                Some(statement) => {
-                    statements.push(statement);
-                    self.consume_token();
+        match token.kind {
+            TokenType::While => {
+                self.consume_token();
+                let condition =
+                    self.expect_expression(Precedence::LOWEST, "as the condition of 'while'");
+                let body = self.parse_loop_body(name, "while");
+                Statement::WhileStatement {
+                    span: start.to(body.span()),
+                    token,
+                    label,
+                    condition,
+                    body,
+                }
+            }
+            TokenType::For => {
+                if !self.expect_ident_after("'for'") {
+                    return Statement::Error { span: start };
+                }
+                let idents = self.parse_ident_literals();
+                let variables_span = span_of_tokens(&idents);
+                if idents.len() > 2 {
+                    self.report(
+                        variables_span,
+                        format!(
+                            "A 'for' loop binds a value, or a key and a value, but found {} names.",
+                            idents.len()
+                        ),
+                    );
+                }
+                if !self.expect_peek(TokenType::In, "following the names in a 'for' loop") {
+                    return Statement::Error { span: start };
+                }
+                self.consume_token();
+                let iterable = self.expect_expression(Precedence::LOWEST, "after 'in'");
+                let body = self.parse_loop_body(name, "for");
+                Statement::ForStatement {
+                    span: start.to(body.span()),
+                    token,
+                    label,
+                    variables: Box::new(Expression::DefinitionIdentifier {
+                        idents,
+                        span: variables_span,
+                    }),
+                    iterable,
+                    body,
+                }
+            }
+            _ => {
+                let body = self.parse_loop_body(name, "loop");
+                Statement::LoopStatement {
+                    span: start.to(body.span()),
+                    token,
+                    label,
+                    body,
                 }
-                None => self.consume_token(),
             }
         }
-
-        return Statement::BlockStatement { token, statements };
     }
-}
-
-impl<'a> Parser<'a> {
-    pub fn parse_expression(
-        &mut self,
-        precedence: Precedence,
-        idents: Option<Vec<Token<'a>>>,
-    ) -> Option<Box<Expression<'a>>> {
-        let prefix_tok = self.get_current_token().unwrap().kind;
 
-        let prefix = self.prefix_fn(prefix_tok, false, None);
-
-        if !prefix.0 {
-            return None;
+    /// Parses the block after the head of a loop, inside which `break` and
+    /// `continue` can refer to the loop.
+    fn parse_loop_body(&mut self, label: Option<&'a str>, keyword: &str) -> Box<Statement<'a>> {
+        if !self.expect_peek(
+            TokenType::LBrace,
+            &format!("starting the body of '{}'", keyword),
+        ) {
+            return Box::new(Statement::Error {
+                span: self.current_span(),
+            });
         }
 
-        let mut prefix_exp = self.prefix_fn(prefix_tok, true, idents).1.unwrap();
-
-        while precedence
-            < self
-                .get_peek_precedence()
-                .unwrap_or_else(|| Precedence::LOWEST)
-        {
-            let peek = self.get_peek_token().unwrap().kind;
-
-            let (infix_exists, _) = self.infix_fn(peek, false, None);
+        self.loops.push(label);
+        let body = self.parse_block_statement(TokenType::RBrace);
+        self.loops.pop();
+        return Box::new(body);
+    }
 
-            if !infix_exists {
-                return Some(prefix_exp);
+    /// Parses `break`, followed by an optional label and an optional value.
+    /// A name after `break` is taken as a label when an enclosing loop has
+    /// that label, and as the value otherwise.
+    pub fn parse_break_statement(&mut self) -> Statement<'a> {
+        let token = self.get_current_token().unwrap();
+        self.check_inside_loop(&token, "break");
+
+        let label = match self.get_peek_token() {
+            Some(
+                peek @ Token {
+                    kind: TokenType::Ident(name),
+                    ..
+                },
+            ) if self.loops.contains(&Some(name)) => {
+                self.consume_token();
+                Some(peek)
             }
+            _ => None,
+        };
 
+        let value = if self.peek_ends_statement() {
+            None
+        } else {
             self.consume_token();
-            prefix_exp = self
-                .infix_fn(
-                    self.get_current_token().unwrap().kind,
-                    true,
-                    Some(prefix_exp),
-                )
-                .1
-                .unwrap();
+            Some(self.expect_expression(Precedence::LOWEST, "after 'break'"))
+        };
+
+        return Statement::BreakStatement {
+            span: token.span.to(self.current_span()),
+            token,
+            label,
+            value,
+        };
+    }
+
+    /// Parses `continue`, followed by an optional label.
+    pub fn parse_continue_statement(&mut self) -> Statement<'a> {
+        let token = self.get_current_token().unwrap();
+        self.check_inside_loop(&token, "continue");
+
+        let label = match self.get_peek_token() {
+            Some(
+                peek @ Token {
+                    kind: TokenType::Ident(name),
+                    ..
+                },
+            ) => {
+                self.consume_token();
+                if !self.loops.is_empty() && !self.loops.contains(&Some(name)) {
+                    self.report(
+                        peek.span,
+                        format!("There is no enclosing loop labelled '{}'.", name),
+                    );
+                }
+                Some(peek)
+            }
+            _ => None,
+        };
+
+        return Statement::ContinueStatement {
+            span: token.span.to(self.current_span()),
+            token,
+            label,
+        };
+    }
+
+    /// Reports `break` or `continue` outside of a loop.
+    fn check_inside_loop(&mut self, token: &Token<'a>, keyword: &str) {
+        if self.loops.is_empty() {
+            self.report(
+                token.span,
+                format!("Found '{}' outside of a loop.", keyword),
+            );
+        }
+    }
+
+    /// Whether the token after the current one ends the statement.
+    fn peek_ends_statement(&self) -> bool {
+        return matches!(
+            self.get_peek_token().map(|peek| peek.kind),
+            None | Some(TokenType::Newline) | Some(TokenType::Semicolon) | Some(TokenType::RBrace)
+        );
+    }
+
+    pub fn parse_return_statement(&mut self) -> Statement<'a> {
+        let token = self.get_current_token().unwrap();
+
+        let value = if self.peek_ends_statement() {
+            None
+        } else {
+            self.consume_token();
+            Some(self.expect_expression(Precedence::LOWEST, "after 'return'"))
+        };
+
+        return Statement::ReturnStatement {
+            span: token.span.to(self.current_span()),
+            token,
+            value,
+        };
+    }
+
+    /// Parses `if cond { ... }`, followed by any number of `else if` branches
+    /// and an optional final `else { ... }`. The `else` may start a new line.
+    pub fn parse_if_expression(&mut self) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        self.consume_token();
+        let condition = self.expect_expression(Precedence::LOWEST, "as the condition of 'if'");
+        if !self.expect_peek(TokenType::LBrace, "starting the body of 'if'") {
+            return self.error_expression();
+        }
+        let consequence = self.parse_block_statement(TokenType::RBrace);
+
+        let mut newlines = 0;
+        while let Some(Token {
+            kind: TokenType::Newline,
+            ..
+        }) = self.tokens.get(self.current_position + 1 + newlines)
+        {
+            newlines += 1;
+        }
+        let alternative = match self.tokens.get(self.current_position + 1 + newlines) {
+            Some(Token {
+                kind: TokenType::Else,
+                ..
+            }) => {
+                self.current_position += newlines + 1;
+                Some(self.parse_else_branch())
+            }
+            _ => None,
+        };
+
+        let end = alternative
+            .as_ref()
+            .map_or(consequence.span(), |alternative| alternative.span());
+        return Box::new(Expression::IfExpression {
+            span: token.span.to(end),
+            token,
+            condition,
+            consequence: Box::new(consequence),
+            alternative: alternative.map(Box::new),
+        });
+    }
+
+    /// Parses what follows an `else`: another `if`, or a block.
+    fn parse_else_branch(&mut self) -> Statement<'a> {
+        match self.get_peek_token() {
+            Some(
+                token @ Token {
+                    kind: TokenType::If,
+                    ..
+                },
+            ) => {
+                self.consume_token();
+                let expression = self.parse_if_expression();
+                Statement::ExpressionStatement {
+                    token,
+                    span: expression.span(),
+                    expression,
+                }
+            }
+            _ => {
+                if !self.expect_peek(TokenType::LBrace, "starting the body of 'else'") {
+                    return Statement::Error {
+                        span: self.current_span(),
+                    };
+                }
+                self.parse_block_statement(TokenType::RBrace)
+            }
         }
+    }
+
+    pub fn parse_block_statement(&mut self, end_type: TokenType<'a>) -> Statement<'a> {
+        let token = self.get_current_token().unwrap();
 
-        return Some(prefix_exp);
+        self.consume_token();
+        let statements = self.parse_statements(Some(&end_type));
+
+        if self.get_current_token().is_none() {
+            self.error(
+                token.span,
+                format!(
+                    "This block is never closed; add the missing '{:?}'.",
+                    end_type
+                ),
+            );
+        }
+
+        return Statement::BlockStatement {
+            span: token.span.to(self.current_span()),
+            token,
+            statements,
+        };
     }
+}
+
+impl<'a> Parser<'a> {
+    /// Fills the parselet tables. Each prefix parselet is keyed by the token
+    /// kind that starts its expression, each infix rule by its operator.
+    fn register_parselets(&mut self) {
+        use Associativity::{Left, Right};
+        use TokenType::*;
+
+        self.prefix(Integer(0), |parser| parser.parse_integer_literal());
+        self.prefix(Float(0.0), |parser| parser.parse_float_literal());
+        self.prefix(String("".into()), |parser| parser.parse_string_literal());
+        self.prefix(StringHead("".into()), |parser| {
+            parser.parse_interpolated_string()
+        });
+        self.prefix(True, |parser| parser.parse_boolean_literal());
+        self.prefix(False, |parser| parser.parse_boolean_literal());
+        self.prefix(Nil, |parser| parser.parse_nil_literal());
+        self.prefix(Underscore, |parser| parser.parse_underscore_literal());
+        self.prefix(Ident(""), |parser| parser.parse_ident_literal());
+        self.prefix(LParen, |parser| parser.parse_grouped_expression());
+        self.prefix(LBracket, |parser| parser.parse_array_literal());
+        self.prefix(LBrace, |parser| parser.parse_map_literal());
+        self.prefix(Bar, |parser| {
+            // `break` and `continue` in a function cannot reach loops outside it.
+            let loops = mem::take(&mut parser.loops);
+            let function = parser.parse_function_expression();
+            parser.loops = loops;
+            function
+        });
+        self.prefix(If, |parser| parser.parse_if_expression());
+        for kind in [Minus, Bang, Tilde] {
+            self.prefix(kind, |parser| parser.parse_prefix_expression());
+        }
 
-    pub fn prefix_fn(
+        let binary: InfixParselet<'a> = |parser, left| parser.parse_infix_expression(left);
+        self.infix(Or, Precedence::OR, Left, binary);
+        self.infix(And, Precedence::AND, Left, binary);
+        for kind in [DoubleEqual, BangEqual] {
+            self.infix(kind, Precedence::EQUALS, Left, binary);
+        }
+        for kind in [LT, GT, LTEq, GTEq] {
+            self.infix(kind, Precedence::LESSGREATER, Left, binary);
+        }
+        self.infix(Bar, Precedence::BITOR, Left, binary);
+        self.infix(Caret, Precedence::BITXOR, Left, binary);
+        self.infix(Ampersand, Precedence::BITAND, Left, binary);
+        for kind in [ShiftLeft, ShiftRight] {
+            self.infix(kind, Precedence::SHIFT, Left, binary);
+        }
+        for kind in [Plus, Minus] {
+            self.infix(kind, Precedence::SUM, Left, binary);
+        }
+        for kind in [Asterisk, Slash, Percent] {
+            self.infix(kind, Precedence::PRODUCT, Left, binary);
+        }
+        self.infix(DoubleAsterisk, Precedence::POWER, Right, binary);
+        self.infix(LBracket, Precedence::INDEX, Left, |parser, left| {
+            parser.parse_index_expression(left)
+        });
+        self.infix(Period, Precedence::INDEX, Left, |parser, left| {
+            parser.parse_member_access(left)
+        });
+        self.infix(LParen, Precedence::CALL, Left, |parser, left| {
+            parser.parse_call_expression(left)
+        });
+        self.infix(Match, Precedence::MATCH, Left, |parser, left| {
+            parser.parse_match_expression(left)
+        });
+    }
+
+    fn prefix(&mut self, kind: TokenType<'a>, parselet: PrefixParselet<'a>) {
+        self.prefix_parselets.insert(discriminant(&kind), parselet);
+    }
+
+    fn infix(
         &mut self,
         kind: TokenType<'a>,
-        execute: bool,
-        idents: Option<Vec<Token<'a>>>,
-    ) -> (bool, Option<Box<Expression<'a>>>) {
-        match (kind, execute) {
-            (TokenType::Integer(_), false) => (true, None),
-            (TokenType::Integer(_), true) => (true, Some(self.parse_integer_literal())),
-            (TokenType::Underscore, false) => (true, None),
-            (TokenType::Underscore, true) => (true, Some(self.parse_underscore_literal())),
-            (TokenType::Float(_), false) => (true, None),
-            (TokenType::Float(_), true) => (true, Some(self.parse_float_literal())),
-            (TokenType::String(_), false) => (true, None),
-            (TokenType::String(_), true) => (true, Some(self.parse_string_literal())),
-            (TokenType::LParen, false) => (true, None),
-            (TokenType::LParen, true) => (true, Some(self.parse_grouped_expression())),
-            (TokenType::LBracket, false) => (true, None),
-            (TokenType::LBracket, true) => (true, Some(self.parse_array_literal())),
-            (TokenType::Bar, false) => (true, None),
-            (TokenType::Bar, true) => (true, Some(self.parse_function_expression())),
-            (TokenType::Ident(_), false) => (true, None),
-            (TokenType::Ident(_), true) => (true, Some(self.parse_ident_literal(idents))),
-            _ => (false, None),
-        }
-    }
-
-    pub fn infix_fn(
-        &mut self,
-        kind: TokenType,
-        execute: bool,
-        left: Option<Box<Expression<'a>>>,
-    ) -> (bool, Option<Box<Expression<'a>>>) {
-        match (kind, execute) {
-            (TokenType::Plus, false) => (true, None),
-            (TokenType::Plus, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::Minus, false) => (true, None),
-            (TokenType::Minus, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::Slash, false) => (true, None),
-            (TokenType::Slash, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::Asterisk, false) => (true, None),
-            (TokenType::Asterisk, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::Minus, false) => (true, None),
-            (TokenType::Minus, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-
-            (TokenType::PlusEqual, false) => (true, None),
-            (TokenType::PlusEqual, true) => {
-                (true, Some(self.parse_infix_expression(left.unwrap())))
-            }
-            (TokenType::AsteriskEqual, false) => (true, None),
-            (TokenType::AsteriskEqual, true) => {
-                (true, Some(self.parse_infix_expression(left.unwrap())))
-            }
-            (TokenType::SlashEqual, false) => (true, None),
-            (TokenType::SlashEqual, true) => {
-                (true, Some(self.parse_infix_expression(left.unwrap())))
-            }
-            (TokenType::DoubleEqual, false) => (true, None),
-            (TokenType::DoubleEqual, true) => {
-                (true, Some(self.parse_infix_expression(left.unwrap())))
-            }
-            (TokenType::BangEqual, false) => (true, None),
-            (TokenType::BangEqual, true) => {
-                (true, Some(self.parse_infix_expression(left.unwrap())))
-            }
-            (TokenType::LT, false) => (true, None),
-            (TokenType::LT, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::GT, false) => (true, None),
-            (TokenType::GT, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::Or, false) => (true, None),
-            (TokenType::Or, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::And, false) => (true, None),
-            (TokenType::And, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::LTEq, false) => (true, None),
-            (TokenType::LTEq, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::GTEq, false) => (true, None),
-            (TokenType::GTEq, true) => (true, Some(self.parse_infix_expression(left.unwrap()))),
-            (TokenType::LParen, false) => (true, None),
-            (TokenType::LParen, true) => (true, Some(self.parse_call_expression(left.unwrap()))),
-            (TokenType::Match, false) => (true, None),
-            (TokenType::Match, true) => (true, Some(self.parse_match_expression(left.unwrap()))),
-            _ => (false, None),
+        precedence: Precedence,
+        associativity: Associativity,
+        parselet: InfixParselet<'a>,
+    ) {
+        let rule = InfixRule {
+            precedence,
+            associativity,
+            parselet,
+        };
+        self.infix_rules.insert(discriminant(&kind), rule);
+    }
+
+    pub fn infix_rule(&self, kind: &TokenType<'a>) -> Option<InfixRule<'a>> {
+        self.infix_rules.get(&discriminant(kind)).copied()
+    }
+
+    pub fn parse_expression(&mut self, precedence: Precedence) -> Option<Box<Expression<'a>>> {
+        return self.parse_expression_bp(precedence.binding_power());
+    }
+
+    /// Parses an expression made of operators binding tighter than
+    /// `min_binding_power`, leaving the current token on its last token.
+    fn parse_expression_bp(&mut self, min_binding_power: u8) -> Option<Box<Expression<'a>>> {
+        let prefix_tok = self.get_current_token()?.kind;
+        let prefix = *self.prefix_parselets.get(&discriminant(&prefix_tok))?;
+        let mut left = prefix(self);
+
+        while let Some(rule) = self
+            .get_peek_token()
+            .and_then(|peek| self.infix_rule(&peek.kind))
+        {
+            if rule.left_binding_power() <= min_binding_power {
+                break;
+            }
+
+            self.consume_token();
+            left = (rule.parselet)(self, left);
         }
+
+        return Some(left);
     }
 }
 
 impl<'a> Parser<'a> {
+    /// Parses a unary operator and its operand, which binds tighter than any
+    /// binary operator: `-a * b` is `(-a) * b`.
+    pub fn parse_prefix_expression(&mut self) -> Box<Expression<'a>> {
+        let current = self.get_current_token().unwrap();
+        self.consume_token();
+        let right = self.expect_expression(
+            Precedence::PREFIX,
+            &format!("after prefix '{:?}'", current.kind),
+        );
+        return Box::new(Expression::PrefixExpression {
+            span: current.span.to(right.span()),
+            token: current,
+            right,
+        });
+    }
+
     pub fn parse_infix_expression(&mut self, left: Box<Expression<'a>>) -> Box<Expression<'a>> {
         let current = self.get_current_token().unwrap();
-        let precedence = self.get_current_precedence().unwrap();
+        let rule = self.infix_rule(&current.kind).unwrap();
         self.consume_token();
-        let right = self.parse_expression(precedence, None).unwrap();
+        let right = self.expect_expression_bp(
+            rule.right_binding_power(),
+            &format!("after '{:?}'", current.kind),
+        );
         return Box::new(Expression::InfixExpression {
             token: current,
+            span: left.span().to(right.span()),
             right,
             left,
         });
     }
 
     pub fn parse_grouped_expression(&mut self) -> Box<Expression<'a>> {
-        // self.consume_token();
-        if let Some(s) = self.get_current_token() {
-            if s.kind == TokenType::LParen {
-                self.consume_token();
-            }
+        self.consume_token();
+        let expr = self.expect_expression(Precedence::LOWEST, "after '('");
+        self.expect_peek(TokenType::RParen, "closing a grouped expression");
+
+        return expr;
+    }
+
+    /// Parses `object.name`, with the current token on the `.`.
+    pub fn parse_member_access(&mut self, object: Box<Expression<'a>>) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        if !self.expect_ident_after("'.'") {
+            return self.error_expression();
         }
 
-        let expr = self.parse_expression(Precedence::LOWEST, None);
+        let name = self.get_current_token().unwrap();
+        return Box::new(Expression::MemberAccess {
+            span: object.span().to(name.span),
+            token,
+            object,
+            name,
+        });
+    }
 
-        if let Some(expr) = expr {
-            if let Some(tok) = self.get_peek_token() {
-                self.expect_peek(TokenType::RParen, "defining a grouped expression");
-            } else {
-                if let Some(tok) = self.get_current_token() {
-                    if (tok.kind == TokenType::RParen) {
-                    } else {
-                        error(format!("{} Expected closing parenthesis.", tok.position));
-                    }
+    /// Parses `left[index]`, or a slice `left[start:end:step]` with any of
+    /// the bounds left out, such as `a[:2]` or `a[::-1]`.
+    pub fn parse_index_expression(&mut self, left: Box<Expression<'a>>) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+        let start = self.parse_slice_bound("as the start of a slice");
+
+        if !self.peek_is_kind(&TokenType::Colon) {
+            let index = match start {
+                Some(index) => index,
+                None => {
+                    self.consume_token();
+                    self.error(
+                        self.current_span(),
+                        format!(
+                            "Expected an index inside '[]', received {} instead.",
+                            describe(self.get_current_token().as_ref())
+                        ),
+                    );
+                    return self.error_expression();
                 }
-            }
+            };
+            self.expect_peek(TokenType::RBracket, "closing an index");
+            return Box::new(Expression::IndexExpression {
+                span: left.span().to(self.current_span()),
+                token,
+                left,
+                index,
+            });
+        }
 
-            return expr;
-        } else {
-            error(format!("Expected some expression after opening bracket."));
-            panic!();
+        self.consume_token();
+        let end = self.parse_slice_bound("as the end of a slice");
+        let mut step = None;
+        if self.peek_is_kind(&TokenType::Colon) {
+            self.consume_token();
+            step = self.parse_slice_bound("as the step of a slice");
+        }
+        self.expect_peek(TokenType::RBracket, "closing a slice");
+
+        return Box::new(Expression::SliceExpression {
+            span: left.span().to(self.current_span()),
+            token,
+            left,
+            start,
+            end,
+            step,
+        });
+    }
+
+    /// Parses the slice bound after the current token, if there is one
+    /// before the next `:` or `]`.
+    fn parse_slice_bound(&mut self, reason: &str) -> Option<Box<Expression<'a>>> {
+        match self.get_peek_token().map(|peek| peek.kind) {
+            Some(TokenType::Colon) | Some(TokenType::RBracket) | None => None,
+            Some(_) => {
+                self.consume_token();
+                Some(self.expect_expression(Precedence::LOWEST, reason))
+            }
         }
     }
 
@@ -639,38 +1379,35 @@ impl<'a> Parser<'a> {
     ) -> Box<Expression<'a>> {
         let token = self.get_current_token().unwrap();
 
-        if token.kind != TokenType::LParen {
-            error(format!("{} Function calls must start with token of type 'LParen', received '{:?}' instead.", token.position, token.kind));
-        }
         let parameters = self.parse_expression_list(TokenType::RParen);
         return Box::new(Expression::CallExpression {
             token,
             parameters,
+            span: fn_literal.span().to(self.current_span()),
             function: fn_literal,
         });
     }
 
+    /// Parses comma separated expressions after the current (opening) token,
+    /// up to and including `end`. A trailing comma is allowed.
     fn parse_expression_list(&mut self, end: TokenType) -> Vec<Box<Expression<'a>>> {
         let mut exprs: Vec<Box<Expression<'a>>> = Vec::new();
-        if self.peek_is(end) {
+        if self.peek_is_kind(&end) {
             self.consume_token();
             return exprs;
         }
 
-        self.consume_token();
-
-        exprs.push(self.parse_expression(Precedence::LOWEST, None).unwrap());
-        self.consume_token();
+        loop {
+            self.consume_token();
+            exprs.push(self.expect_expression(Precedence::LOWEST, "in the list"));
 
-        while let Some(tok) = self.get_current_token() {
-            if tok.kind != end {
-                // self.consume_token();
-                if let Some(expr) = self.parse_expression(Precedence::LOWEST, None) {
-                    exprs.push(expr)
-                }
-                // exprs.push(.unwrap());
+            if !self.peek_is_kind(&TokenType::Comma) {
+                self.expect_peek(end, "closing the list");
+                break;
+            }
+            self.consume_token();
+            if self.peek_is_kind(&end) {
                 self.consume_token();
-            } else {
                 break;
             }
         }
@@ -678,142 +1415,899 @@ impl<'a> Parser<'a> {
         return exprs;
     }
 
-    fn parse_match_expression(&mut self, expression: Box<Expression<'a>>) -> Box<Expression<'a>> {
+    /// Parses `{ key: value, ... }`. Entries are separated by a comma,
+    /// newlines, or both. A key is an identifier or a string, taken as
+    /// written, or `[expression]` for a key computed at runtime.
+    pub fn parse_map_literal(&mut self) -> Box<Expression<'a>> {
         let token = self.get_current_token().unwrap();
+        let mut pairs: Vec<(MapKey<'a>, Box<Expression<'a>>)> = Vec::new();
 
-        self.expect_peek(
-            TokenType::LBrace,
-            "defining the opening of a match expression",
-        );
+        let mut separated = true;
+        loop {
+            separated |= self.skip_peek_newlines();
+            if self.peek_is_kind(&TokenType::Comma) {
+                self.consume_token();
+                self.skip_peek_newlines();
+                separated = true;
+            }
 
-        let mut pairs: Vec<MatchPairExpression<'a>> = Vec::new();
-        let mut default: Option<Statement<'a>> = None;
-        self.consume_token();
+            match self.get_peek_token() {
+                Some(Token {
+                    kind: TokenType::RBrace,
+                    ..
+                }) => {
+                    self.consume_token();
+                    break;
+                }
+                Some(peek) if !separated => {
+                    self.error(
+                        peek.span,
+                        format!(
+                            "Expected ',' or a newline between map entries, received '{:?}' instead.",
+                            peek.kind
+                        ),
+                    );
+                    return self.error_expression();
+                }
+                Some(_) => self.consume_token(),
+                None => {
+                    self.error(
+                        self.current_span(),
+                        "Expected '}' to close the map literal.".to_string(),
+                    );
+                    return self.error_expression();
+                }
+            }
+            separated = false;
 
-        while self.get_peek_token().unwrap().kind != TokenType::RBrace {
-            let mut destructures: Vec<Box<Expression<'a>>> = Vec::new();
-            let mut statements: Vec<Statement<'a>> = Vec::new();
-            let expr = self.parse_expression(Precedence::LOWEST, None).unwrap();
-
-            match *expr {
-                Expression::UnderscoreLiteral { token } => {
-                    self.expect_peek(TokenType::Arrow, "defining a match clause");
-
-                    if let Some(peek) = self.get_peek_token() {
-                        match peek.kind {
-                            TokenType::LBrace => {
-                                self.consume_token();
-                                let statement = self.parse_block_statement(TokenType::RBrace);
-                                default = Some(statement);
-                            }
-                            _ => {
-                                let token = self.get_current_token().unwrap();
-                                self.consume_token();
-                                let expression =
-                                    self.parse_expression(Precedence::LOWEST, None).unwrap(); // TODO: Error check
-                                statements.push(Statement::ReturnStatement {
-                                    token,
-                                    value: expression,
-                                });
-                                default = Some(Statement::BlockStatement { statements, token })
-                            }
-                        }
-                    } else {
-                        error(format!(
-                            "{} Expected expression or statement after match predicate.",
-                            self.get_current_token().unwrap().position
-                        ))
-                    }
+            let key = match self.parse_map_key() {
+                Some(key) => key,
+                None => return self.error_expression(),
+            };
+            if !self.expect_peek(TokenType::Colon, "separating a map key from its value") {
+                return self.error_expression();
+            }
+            self.consume_token();
+            let value = self.expect_expression(Precedence::LOWEST, "as a map value");
+
+            if let MapKey::Constant { name, .. } = &key {
+                let duplicate = pairs.iter().any(|(other, _)| {
+                    matches!(other, MapKey::Constant { name: other, .. } if other == name)
+                });
+                if duplicate {
+                    self.report(
+                        key.span(),
+                        format!("The key '{}' appears more than once in this map.", name),
+                    );
+                }
+            }
+            pairs.push((key, value));
+        }
 
-                    if let Some(peek) = self.get_peek_token() {
-                        if peek.kind == TokenType::Comma {
-                            self.consume_token();
-                            self.consume_token();
-                        }
-                    }
+        return Box::new(Expression::MapLiteral {
+            span: token.span.to(self.current_span()),
+            token,
+            pairs,
+        });
+    }
 
-                    continue;
-                }
-                _ => {
-                    destructures.push(expr);
+    /// Parses the map key at the current token, leaving the current token on
+    /// its last token.
+    fn parse_map_key(&mut self) -> Option<MapKey<'a>> {
+        let token = self.get_current_token().unwrap();
+        match &token.kind {
+            TokenType::Ident(name) => Some(MapKey::Constant {
+                name: Cow::Borrowed(*name),
+                token,
+            }),
+            TokenType::String(value) => Some(MapKey::Constant {
+                name: value.clone(),
+                token,
+            }),
+            TokenType::LBracket => {
+                self.consume_token();
+                let key = self.expect_expression(Precedence::LOWEST, "as a computed map key");
+                if !self.expect_peek(TokenType::RBracket, "closing a computed map key") {
+                    return None;
                 }
+                Some(MapKey::Computed(key))
+            }
+            _ => {
+                self.error(
+                    token.span,
+                    format!(
+                        "Expected a map key, received {} instead. Keys are names, strings, or '[expression]'.",
+                        describe(Some(&token))
+                    ),
+                );
+                None
             }
+        }
+    }
 
-            // self.expect_peek(TokenType::Arrow, "defining a match clause");
+    /// Whether the `{` at `position` opens a map literal rather than a block,
+    /// where both can appear, such as a function body. It does when its first
+    /// entry is a key followed by `:`; an empty `{}` is a block.
+    fn brace_starts_map(&self, position: usize) -> bool {
+        let mut position = position + 1;
+        while let Some(TokenType::Newline) = self.tokens.get(position).map(|token| &token.kind) {
+            position += 1;
+        }
 
-            while let Some(peek) = self.get_peek_token() {
-                match peek.kind {
-                    TokenType::Comma => {
-                        self.consume_token();
-                        if let Some(peek2) = self.get_peek_token() {
-                            self.consume_token();
-                            destructures
-                                .push(self.parse_expression(Precedence::LOWEST, None).unwrap());
-                        } else {
-                            error(format!("{} Expected some token after comma", peek.position));
-                            panic!()
-                        }
+        match self.tokens.get(position).map(|token| &token.kind) {
+            Some(TokenType::Ident(_)) | Some(TokenType::String(_)) => position += 1,
+            Some(TokenType::LBracket) => {
+                let mut depth = 0;
+                for token in &self.tokens[position..] {
+                    position += 1;
+                    match token.kind {
+                        TokenType::LBracket => depth += 1,
+                        TokenType::RBracket => depth -= 1,
+                        _ => {}
                     }
-                    _ => {
-                        // self.consume_token();
+                    if depth == 0 {
                         break;
                     }
                 }
             }
+            _ => return false,
+        }
 
-            self.expect_peek(TokenType::Arrow, "defining a match clause");
+        return matches!(
+            self.tokens.get(position).map(|token| &token.kind),
+            Some(TokenType::Colon)
+        );
+    }
 
-            if let Some(peek) = self.get_peek_token() {
-                match peek.kind {
-                    TokenType::LBrace => {
-                        self.consume_token();
-                        let statement = self.parse_block_statement(TokenType::RBrace);
-                        pairs.push(MatchPairExpression {
-                            predicate: destructures,
-                            statement,
-                        });
-                    }
-                    _ => {
-                        let token = self.get_current_token().unwrap();
-                        self.consume_token();
-                        let expression = self.parse_expression(Precedence::LOWEST, None).unwrap(); // TODO: Error check
-                        statements.push(Statement::ReturnStatement {
-                            token,
-                            value: expression,
-                        });
-                        pairs.push(MatchPairExpression {
-                            predicate: destructures,
-                            statement: Statement::BlockStatement { statements, token },
-                        });
-                    }
+    fn parse_match_expression(&mut self, expression: Box<Expression<'a>>) -> Box<Expression<'a>> {
+        let token = self.get_current_token().unwrap();
+
+        if !self.expect_peek(TokenType::LBrace, "opening a match expression") {
+            return self.error_expression();
+        }
+
+        let mut pairs: Vec<MatchPairExpression<'a>> = Vec::new();
+        let mut default: Option<Box<Statement<'a>>> = None;
+        // Set once an unguarded arm has matched every value.
+        let mut catch_all = false;
+
+        // Arms are separated by a comma, newlines, or both.
+        let mut separated = true;
+        loop {
+            separated |= self.skip_peek_newlines();
+            if self.peek_is_kind(&TokenType::Comma) {
+                self.consume_token();
+                self.skip_peek_newlines();
+                separated = true;
+            }
+
+            match self.get_peek_token() {
+                Some(Token {
+                    kind: TokenType::RBrace,
+                    ..
+                }) => {
+                    self.consume_token();
+                    break;
+                }
+                Some(peek) if !separated => {
+                    self.error(
+                        peek.span,
+                        format!(
+                            "Expected ',' or a newline between match arms, received '{:?}' instead.",
+                            peek.kind
+                        ),
+                    );
+                    return self.error_expression();
+                }
+                Some(_) => self.consume_token(),
+                None => {
+                    self.error(
+                        self.current_span(),
+                        "Expected '}' to close the match expression.".to_string(),
+                    );
+                    return self.error_expression();
                 }
-            } else {
-                error(format!(
-                    "{} Expected expression or statement after match predicate.",
-                    self.get_current_token().unwrap().position
-                ))
             }
+            separated = false;
 
-            if let Some(peek) = self.get_peek_token() {
-                if peek.kind == TokenType::Comma {
+            let mut pattern = self.parse_pattern();
+            // Patterns separated by commas are alternatives, as with `|`.
+            if self.peek_is_kind(&TokenType::Comma) {
+                let mut alternatives = vec![pattern];
+                while self.peek_is_kind(&TokenType::Comma) {
                     self.consume_token();
                     self.consume_token();
+                    alternatives.push(self.parse_pattern());
+                }
+                self.check_alternative_bindings(&alternatives);
+                pattern = or_pattern(alternatives);
+            }
+
+            let mut guard = None;
+            if self.peek_is_kind(&TokenType::If) {
+                self.consume_token();
+                self.consume_token();
+                guard =
+                    Some(self.expect_expression(Precedence::LOWEST, "as the guard of a match arm"));
+            }
+
+            if !self.expect_peek(TokenType::Arrow, "defining a match clause") {
+                return self.error_expression();
+            }
+            let statement = self.parse_match_body();
+            if self.recovering {
+                return self.error_expression();
+            }
+
+            if catch_all {
+                self.report(
+                    pattern.span(),
+                    "This match arm is unreachable, as an earlier arm matches every value."
+                        .to_string(),
+                );
+            }
+            if guard.is_none() && matches_everything(&pattern) {
+                catch_all = true;
+            }
+
+            if matches!(pattern, Pattern::Wildcard(_)) && guard.is_none() {
+                // A second `_` arm is unreachable and reported above.
+                if default.is_none() {
+                    default = Some(Box::new(statement));
                 }
+            } else {
+                pairs.push(MatchPairExpression {
+                    span: pattern.span().to(statement.span()),
+                    pattern,
+                    guard,
+                    statement,
+                });
             }
         }
 
+        let end = self.get_current_token().unwrap();
         Box::new(Expression::MatchExpression {
             token,
             default,
             pairs,
+            span: expression.span().to(end.span),
         })
     }
+
+    /// Parses the pattern at the current token, including alternatives
+    /// separated by `|`, leaving the current token on its last token.
+    fn parse_pattern(&mut self) -> Pattern<'a> {
+        let first = self.parse_single_pattern();
+        if !self.peek_is_kind(&TokenType::Bar) {
+            return first;
+        }
+
+        let mut alternatives = vec![first];
+        while self.peek_is_kind(&TokenType::Bar) {
+            self.consume_token();
+            self.consume_token();
+            alternatives.push(self.parse_single_pattern());
+        }
+        self.check_alternative_bindings(&alternatives);
+        return or_pattern(alternatives);
+    }
+
+    /// Reports alternatives that bind different names than the first, as the
+    /// arm could then use a name the matched value never bound.
+    fn check_alternative_bindings(&mut self, alternatives: &[Pattern<'a>]) {
+        let names = |pattern| {
+            let mut names = Vec::new();
+            bound_names(pattern, &mut names);
+            names.sort_unstable();
+            names
+        };
+
+        let expected = names(&alternatives[0]);
+        for alternative in &alternatives[1..] {
+            if names(alternative) != expected {
+                self.report(
+                    alternative.span(),
+                    "Every alternative of a pattern must bind the same names.".to_string(),
+                );
+            }
+        }
+    }
+
+    fn parse_single_pattern(&mut self) -> Pattern<'a> {
+        let token = match self.get_current_token() {
+            Some(token) => token,
+            None => {
+                return self.pattern_error("Expected a pattern, received the end of input instead.")
+            }
+        };
+
+        match token.kind {
+            TokenType::Ident(_) => Pattern::Binding(token),
+            TokenType::Underscore => Pattern::Wildcard(token),
+            TokenType::Integer(_)
+            | TokenType::Float(_)
+            | TokenType::String(_)
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::Minus => self.parse_literal_pattern(),
+            TokenType::LBracket => self.parse_array_pattern(),
+            TokenType::LBrace => self.parse_map_pattern(),
+            TokenType::Ellipsis => self.pattern_error(
+                "A '...' rest pattern can only appear directly inside an array pattern.",
+            ),
+            TokenType::StringHead(_) => {
+                self.pattern_error("An interpolated string cannot be used as a pattern.")
+            }
+            kind => self.pattern_error(&format!(
+                "Expected a pattern, received '{:?}' instead. Patterns are literals, names, '_', arrays, maps and ranges.",
+                kind
+            )),
+        }
+    }
+
+    /// Reports an error at the current token and returns a placeholder
+    /// pattern.
+    fn pattern_error(&mut self, message: &str) -> Pattern<'a> {
+        let span = self.current_span();
+        self.error(span, message.to_string());
+        return Pattern::Error { span };
+    }
+
+    /// Parses a literal pattern, or a range if the literal is followed by
+    /// `..` or `..=`.
+    fn parse_literal_pattern(&mut self) -> Pattern<'a> {
+        let start = match self.parse_pattern_literal() {
+            Some(start) => start,
+            None => {
+                return Pattern::Error {
+                    span: self.current_span(),
+                }
+            }
+        };
+
+        let (token, inclusive) = match self.get_peek_token() {
+            Some(
+                token @ Token {
+                    kind: TokenType::DotDot,
+                    ..
+                },
+            ) => (token, false),
+            Some(
+                token @ Token {
+                    kind: TokenType::DotDotEqual,
+                    ..
+                },
+            ) => (token, true),
+            _ => return Pattern::Literal(start),
+        };
+        self.consume_token();
+
+        if matches!(
+            *start,
+            Expression::BooleanLiteral { .. } | Expression::NilLiteral { .. }
+        ) {
+            self.error(
+                start.span(),
+                "A range must start with a number or a string.".to_string(),
+            );
+            return Pattern::Error { span: start.span() };
+        }
+
+        self.consume_token();
+        let end = match self.get_current_token().map(|token| token.kind) {
+            Some(TokenType::Integer(_))
+            | Some(TokenType::Float(_))
+            | Some(TokenType::String(_))
+            | Some(TokenType::Minus) => match self.parse_pattern_literal() {
+                Some(end) => end,
+                None => {
+                    return Pattern::Error {
+                        span: self.current_span(),
+                    }
+                }
+            },
+            _ => {
+                let message = format!(
+                    "Expected a number or a string to end the range, received {} instead.",
+                    describe(self.get_current_token().as_ref())
+                );
+                return self.pattern_error(&message);
+            }
+        };
+
+        return Pattern::Range {
+            span: start.span().to(end.span()),
+            token,
+            start,
+            end,
+            inclusive,
+        };
+    }
+
+    /// Parses the literal at the current token: a number, possibly negated,
+    /// a string, a boolean or nil.
+    fn parse_pattern_literal(&mut self) -> Option<Box<Expression<'a>>> {
+        let token = self.get_current_token().unwrap();
+        let literal = match token.kind {
+            TokenType::Integer(_) => self.parse_integer_literal(),
+            TokenType::Float(_) => self.parse_float_literal(),
+            TokenType::String(_) => self.parse_string_literal(),
+            TokenType::True | TokenType::False => self.parse_boolean_literal(),
+            TokenType::Nil => self.parse_nil_literal(),
+            _ => {
+                let right = match self.get_peek_token().map(|peek| peek.kind) {
+                    Some(TokenType::Integer(_)) => {
+                        self.consume_token();
+                        self.parse_integer_literal()
+                    }
+                    Some(TokenType::Float(_)) => {
+                        self.consume_token();
+                        self.parse_float_literal()
+                    }
+                    _ => {
+                        let peek = self.get_peek_token();
+                        self.error(
+                            peek.as_ref().map_or(token.span, |peek| peek.span),
+                            format!(
+                                "Expected a number after '-' in a pattern, received {} instead.",
+                                describe(peek.as_ref())
+                            ),
+                        );
+                        return None;
+                    }
+                };
+                Box::new(Expression::PrefixExpression {
+                    span: token.span.to(right.span()),
+                    token,
+                    right,
+                })
+            }
+        };
+        return Some(literal);
+    }
+
+    /// Parses `[a, b, ...rest]`, where at most one element is a rest pattern.
+    fn parse_array_pattern(&mut self) -> Pattern<'a> {
+        let token = self.get_current_token().unwrap();
+        let mut elements: Vec<Pattern<'a>> = Vec::new();
+
+        if self.peek_is_kind(&TokenType::RBracket) {
+            self.consume_token();
+        } else {
+            loop {
+                self.consume_token();
+                let element = match self.get_current_token().map(|token| token.kind) {
+                    Some(TokenType::Ellipsis) => {
+                        if elements.iter().any(|e| matches!(e, Pattern::Rest { .. })) {
+                            return self
+                                .pattern_error("Only one '...' is allowed in an array pattern.");
+                        }
+                        self.parse_rest_pattern()
+                    }
+                    _ => self.parse_pattern(),
+                };
+                elements.push(element);
+
+                if !self.peek_is_kind(&TokenType::Comma) {
+                    if !self.expect_peek(TokenType::RBracket, "closing an array pattern") {
+                        return Pattern::Error { span: token.span };
+                    }
+                    break;
+                }
+                self.consume_token();
+                if self.peek_is_kind(&TokenType::RBracket) {
+                    self.consume_token();
+                    break;
+                }
+            }
+        }
+
+        return Pattern::Array {
+            span: token.span.to(self.current_span()),
+            token,
+            elements,
+        };
+    }
+
+    /// Parses `...` with an optional name for the remaining elements.
+    fn parse_rest_pattern(&mut self) -> Pattern<'a> {
+        let token = self.get_current_token().unwrap();
+        let name = match self.get_peek_token() {
+            Some(
+                name @ Token {
+                    kind: TokenType::Ident(_),
+                    ..
+                },
+            ) => {
+                self.consume_token();
+                Some(name)
+            }
+            _ => None,
+        };
+
+        return Pattern::Rest {
+            span: token.span.to(self.current_span()),
+            token,
+            name,
+        };
+    }
+
+    /// Parses `{ name: pattern, "key": pattern, shorthand }`. Entries are
+    /// separated by commas and may span lines.
+    fn parse_map_pattern(&mut self) -> Pattern<'a> {
+        let token = self.get_current_token().unwrap();
+        let mut fields: Vec<(MapKey<'a>, Pattern<'a>)> = Vec::new();
+
+        loop {
+            self.skip_peek_newlines();
+            if self.peek_is_kind(&TokenType::RBrace) {
+                self.consume_token();
+                break;
+            }
+
+            self.consume_token();
+            let key_token = match self.get_current_token() {
+                Some(key_token) => key_token,
+                None => return self.pattern_error("Expected '}' to close the map pattern."),
+            };
+            let key = match &key_token.kind {
+                TokenType::Ident(name) => MapKey::Constant {
+                    name: Cow::Borrowed(*name),
+                    token: key_token.clone(),
+                },
+                TokenType::String(value) => MapKey::Constant {
+                    name: value.clone(),
+                    token: key_token.clone(),
+                },
+                kind => {
+                    return self.pattern_error(&format!(
+                        "Expected a key in a map pattern, received '{:?}' instead. Keys are names or strings.",
+                        kind
+                    ))
+                }
+            };
+
+            let pattern = if self.peek_is_kind(&TokenType::Colon) {
+                self.consume_token();
+                self.consume_token();
+                self.parse_pattern()
+            } else if let TokenType::Ident(_) = key_token.kind {
+                Pattern::Binding(key_token)
+            } else {
+                self.expect_peek(TokenType::Colon, "following a string key in a map pattern");
+                return Pattern::Error { span: token.span };
+            };
+            fields.push((key, pattern));
+
+            self.skip_peek_newlines();
+            if self.peek_is_kind(&TokenType::Comma) {
+                self.consume_token();
+                continue;
+            }
+            if !self.expect_peek(TokenType::RBrace, "closing a map pattern") {
+                return Pattern::Error { span: token.span };
+            }
+            break;
+        }
+
+        return Pattern::Map {
+            span: token.span.to(self.current_span()),
+            token,
+            fields,
+        };
+    }
+
+    /// Parses what follows the `->` of a match arm: a block, or a single
+    /// expression that becomes the block's return value.
+    fn parse_match_body(&mut self) -> Statement<'a> {
+        if self.peek_is_kind(&TokenType::LBrace)
+            && !self.brace_starts_map(self.current_position + 1)
+        {
+            self.consume_token();
+            return self.parse_block_statement(TokenType::RBrace);
+        }
+
+        let token = self.get_current_token().unwrap();
+        self.consume_token();
+        let expression = self.expect_expression(Precedence::LOWEST, "after '->'");
+        let span = token.span.to(expression.span());
+        Statement::BlockStatement {
+            statements: vec![Statement::ReturnStatement {
+                token: token.clone(),
+                value: Some(expression),
+                span,
+            }],
+            token,
+            span,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::ast::{Expression, InterpolationPart, MapKey, Pattern, Program, Statement};
+    use crate::errors::ParseError;
     use crate::lexer::Lexer;
     use crate::parser::Parser;
+    use crate::token::{Token, TokenType};
+
+    /// Renders an expression as an S-expression, e.g. `(Plus a (Asterisk b c))`.
+    fn sexp(expression: &Expression) -> String {
+        match expression {
+            Expression::InfixExpression {
+                token, left, right, ..
+            } => format!("({:?} {} {})", token.kind, sexp(left), sexp(right)),
+            Expression::PrefixExpression { token, right, .. } => {
+                format!("({:?} {})", token.kind, sexp(right))
+            }
+            Expression::NormalIdentifier { token, .. } => match token.kind {
+                TokenType::Ident(name) => name.to_string(),
+                _ => unreachable!(),
+            },
+            Expression::MemberAccess { object, name, .. } => match name.kind {
+                TokenType::Ident(name) => format!("{}.{}", sexp(object), name),
+                _ => unreachable!(),
+            },
+            Expression::IntegerLiteral { token, .. } => match token.kind {
+                TokenType::Integer(n) => n.to_string(),
+                _ => unreachable!(),
+            },
+            Expression::FloatLiteral { token, .. } => match token.kind {
+                TokenType::Float(n) => n.to_string(),
+                _ => unreachable!(),
+            },
+            Expression::BooleanLiteral { token, .. } => format!("{:?}", token.kind).to_lowercase(),
+            Expression::CallExpression {
+                function,
+                parameters,
+                ..
+            } => {
+                let parameters = parameters.iter().map(|p| sexp(p)).collect::<Vec<_>>();
+                format!("{}({})", sexp(function), parameters.join(", "))
+            }
+            Expression::InterpolatedString { parts, .. } => {
+                let parts = parts
+                    .iter()
+                    .map(|part| match part {
+                        InterpolationPart::Literal(text) => format!("{:?}", text),
+                        InterpolationPart::Expression(expression) => sexp(expression),
+                    })
+                    .collect::<Vec<_>>();
+                format!("(Interpolate {})", parts.join(" "))
+            }
+            Expression::NilLiteral { .. } => "nil".to_string(),
+            Expression::MatchExpression { pairs, default, .. } => {
+                let mut arms = pairs
+                    .iter()
+                    .map(|pair| {
+                        let guard = pair
+                            .guard
+                            .as_ref()
+                            .map(|guard| format!(" if {}", sexp(guard)));
+                        format!(
+                            "{}{} -> {}",
+                            pattern_sexp(&pair.pattern),
+                            guard.unwrap_or_default(),
+                            statement_sexp(&pair.statement)
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                if let Some(default) = default {
+                    arms.push(format!("_ -> {}", statement_sexp(default)));
+                }
+                format!("(Match {})", arms.join(", "))
+            }
+            Expression::DefinitionIdentifier { idents, .. } => idents
+                .iter()
+                .map(|ident| match ident.kind {
+                    TokenType::Ident(name) => name,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            Expression::ArrayLiteral { elements, .. } => {
+                let elements = elements.iter().map(|e| sexp(e)).collect::<Vec<_>>();
+                format!("[{}]", elements.join(", "))
+            }
+            Expression::IndexExpression { left, index, .. } => {
+                format!("(Index {} {})", sexp(left), sexp(index))
+            }
+            Expression::SliceExpression {
+                left,
+                start,
+                end,
+                step,
+                ..
+            } => {
+                let bound = |bound: &Option<Box<Expression>>| match bound {
+                    Some(bound) => sexp(bound),
+                    None => "_".to_string(),
+                };
+                format!(
+                    "(Slice {} {} {} {})",
+                    sexp(left),
+                    bound(start),
+                    bound(end),
+                    bound(step)
+                )
+            }
+            Expression::StringLiteral { token, .. } => match &token.kind {
+                TokenType::String(value) => format!("{:?}", value),
+                _ => unreachable!(),
+            },
+            Expression::MapLiteral { pairs, .. } => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| match key {
+                        MapKey::Constant { name, .. } => format!("{:?}: {}", name, sexp(value)),
+                        MapKey::Computed(key) => format!("[{}]: {}", sexp(key), sexp(value)),
+                    })
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", pairs.join(", "))
+            }
+            Expression::FunctionLiteral { statements, .. } => {
+                format!("(Function {})", statement_sexp(statements))
+            }
+            Expression::IfExpression {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => match alternative {
+                Some(alternative) => format!(
+                    "(If {} {} {})",
+                    sexp(condition),
+                    statement_sexp(consequence),
+                    statement_sexp(alternative)
+                ),
+                None => format!("(If {} {})", sexp(condition), statement_sexp(consequence)),
+            },
+            other => format!("{:?}", other),
+        }
+    }
+
+    /// Renders a block as `[a; b]` and an expression statement as its
+    /// expression.
+    fn statement_sexp(statement: &Statement) -> String {
+        match statement {
+            Statement::BlockStatement { statements, .. } => {
+                let statements = statements.iter().map(statement_sexp).collect::<Vec<_>>();
+                format!("[{}]", statements.join("; "))
+            }
+            Statement::UpdateStatement {
+                token,
+                ident,
+                expression,
+                ..
+            } => format!("({:?} {} {})", token.kind, sexp(ident), sexp(expression)),
+            Statement::WhileStatement {
+                label,
+                condition,
+                body,
+                ..
+            } => format!(
+                "({}While {} {})",
+                label_sexp(label),
+                sexp(condition),
+                statement_sexp(body)
+            ),
+            Statement::ForStatement {
+                label,
+                variables,
+                iterable,
+                body,
+                ..
+            } => format!(
+                "({}For {} {} {})",
+                label_sexp(label),
+                sexp(variables),
+                sexp(iterable),
+                statement_sexp(body)
+            ),
+            Statement::LoopStatement { label, body, .. } => {
+                format!("({}Loop {})", label_sexp(label), statement_sexp(body))
+            }
+            Statement::BreakStatement { label, value, .. } => {
+                let value = value.as_ref().map(|value| format!(" {}", sexp(value)));
+                format!("({}Break{})", label_sexp(label), value.unwrap_or_default())
+            }
+            Statement::ContinueStatement { label, .. } => {
+                format!("({}Continue)", label_sexp(label))
+            }
+            Statement::ExpressionStatement { expression, .. }
+            | Statement::ReturnStatement {
+                value: Some(expression),
+                ..
+            } => sexp(expression),
+            Statement::ReturnStatement { value: None, .. } => "(Return)".to_string(),
+            other => format!("{:?}", other),
+        }
+    }
+
+    /// Renders a pattern much as it is written, with or-patterns and ranges
+    /// parenthesized.
+    fn pattern_sexp(pattern: &Pattern) -> String {
+        let name = |token: &Token| match token.kind {
+            TokenType::Ident(name) => name.to_string(),
+            _ => unreachable!(),
+        };
+        match pattern {
+            Pattern::Literal(literal) => sexp(literal),
+            Pattern::Binding(token) => name(token),
+            Pattern::Wildcard(_) => "_".to_string(),
+            Pattern::Array { elements, .. } => {
+                let elements = elements.iter().map(pattern_sexp).collect::<Vec<_>>();
+                format!("[{}]", elements.join(", "))
+            }
+            Pattern::Rest { name: rest, .. } => {
+                format!("...{}", rest.as_ref().map(name).unwrap_or_default())
+            }
+            Pattern::Map { fields, .. } => {
+                let fields = fields
+                    .iter()
+                    .map(|(key, pattern)| match key {
+                        MapKey::Constant { name, .. } => {
+                            format!("{:?}: {}", name, pattern_sexp(pattern))
+                        }
+                        MapKey::Computed(_) => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", fields.join(", "))
+            }
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+                ..
+            } => {
+                let operator = if *inclusive { "..=" } else { ".." };
+                format!("({}{}{})", sexp(start), operator, sexp(end))
+            }
+            Pattern::Or { alternatives, .. } => {
+                let alternatives = alternatives.iter().map(pattern_sexp).collect::<Vec<_>>();
+                format!("({})", alternatives.join(" | "))
+            }
+            Pattern::Error { .. } => "error".to_string(),
+        }
+    }
+
+    /// Renders a loop label as `label: `.
+    fn label_sexp(label: &Option<Token>) -> String {
+        match label.as_ref().map(|label| &label.kind) {
+            Some(TokenType::Ident(name)) => format!("{}: ", name),
+            _ => String::new(),
+        }
+    }
+
+    /// Parses `tokens`, failing the test on any diagnostic.
+    fn parse_clean(tokens: Vec<Token>) -> Program {
+        let result = Parser::new(tokens).parse_program();
+        assert!(
+            result.diagnostics.is_empty(),
+            "unexpected diagnostics: {:?}",
+            result.diagnostics
+        );
+        result.program
+    }
+
+    /// Parses `input`, returning only its diagnostics.
+    fn diagnostics(input: &str) -> Vec<ParseError> {
+        let (tokens, _) = Lexer::new(input).tokenize();
+        Parser::new(tokens).parse_program().diagnostics
+    }
+
+    /// Parses a single expression statement and renders it with `sexp`.
+    fn parse_sexp(input: &str) -> String {
+        let (tokens, errors) = Lexer::new(input).tokenize();
+        assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+
+        let program = parse_clean(tokens);
+        match &program.statements[..] {
+            [Statement::ExpressionStatement { expression, .. }] => sexp(expression),
+            statements => panic!("expected one expression, got {:?}", statements),
+        }
+    }
     #[test]
     fn full_test() {
         let test_str = r#"
@@ -821,9 +2315,18 @@ mod tests {
         2 + 2 * 3
         "#;
         let l = Lexer::new(test_str);
-        let z = l.collect::<Vec<_>>();
-
-        let mut p = Parser::new(z);
+        let (z, _) = l.tokenize();
+
+        let program = parse_clean(z);
+        assert_eq!(program.statements.len(), 2);
+        assert!(matches!(
+            program.statements[0],
+            Statement::ImportStatement { .. }
+        ));
+        assert_eq!(
+            statement_sexp(&program.statements[1]),
+            "(Plus 2 (Asterisk 2 3))"
+        );
     }
 
     #[test]
@@ -833,6 +2336,22 @@ mod tests {
         z, x := (15 + 2) * 3
         z = 5
         "#;
+
+        let (lexer, _) = Lexer::new(test_str).tokenize();
+        let program = parse_clean(lexer);
+        assert_eq!(program.statements.len(), 3);
+        match &program.statements[1] {
+            Statement::AssignStatement {
+                defined,
+                expression,
+                ..
+            } => {
+                assert_eq!(sexp(defined), "z, x");
+                assert_eq!(sexp(expression), "(Asterisk (Plus 15 2) 3)");
+            }
+            other => panic!("expected a definition, got {:?}", other),
+        }
+        assert_eq!(statement_sexp(&program.statements[2]), "(Equal z 5)");
     }
 
     #[test]
@@ -845,8 +2364,8 @@ mod tests {
         z := "hi"
         "#;
 
-        let lexer = Lexer::new(test_str).collect::<Vec<_>>();
-        let parser = Parser::new(lexer).parse_program();
+        let (lexer, _) = Lexer::new(test_str).tokenize();
+        let parser = parse_clean(lexer);
         println!("{:#?}", parser);
     }
 
@@ -856,9 +2375,24 @@ mod tests {
         x := |n| -> x match { 5, x -> "five", x, _ -> "z", _ -> "not five :)"}
         "#;
 
-        let lexer = Lexer::new(test_str).collect::<Vec<_>>();
-        let parser = Parser::new(lexer).parse_program();
-        println!("{:#?}", parser);
+        let messages = diagnostics(test_str)
+            .into_iter()
+            .map(|error| error.message)
+            .collect::<Vec<_>>();
+        let mismatch = "Every alternative of a pattern must bind the same names.";
+        let unreachable = "This match arm is unreachable, as an earlier arm matches every value.";
+        assert_eq!(
+            messages,
+            vec![mismatch, mismatch, unreachable, unreachable],
+            "parsing {}",
+            test_str
+        );
+
+        let test_str = r#"
+        x := |n| -> x match { 5, 6 -> "five", [x] | [_, x] -> x, _ -> "not five :)"}
+        "#;
+        let (lexer, _) = Lexer::new(test_str).tokenize();
+        parse_clean(lexer);
     }
 
     #[test]
@@ -868,8 +2402,8 @@ mod tests {
         mapped := (arr.map((|v| -> v + 2)))
         "#;
 
-        let lexer = Lexer::new(test_str).collect::<Vec<_>>();
-        let parser = Parser::new(lexer).parse_program();
+        let (lexer, _) = Lexer::new(test_str).tokenize();
+        let parser = parse_clean(lexer);
         println!("{:#?}", parser);
     }
     #[test]
@@ -879,8 +2413,717 @@ mod tests {
         
         "#;
 
-        let lexer = Lexer::new(test_str).collect::<Vec<_>>();
-        let parser = Parser::new(lexer).parse_program();
+        let (lexer, _) = Lexer::new(test_str).tokenize();
+        let parser = parse_clean(lexer);
         println!("{:#?}", parser);
     }
+
+    #[test]
+    fn spans_cover_whole_nodes() {
+        let test_str = "x := foo(1, 2) + 3";
+
+        let (tokens, _) = Lexer::new(test_str).tokenize();
+        let program = parse_clean(tokens);
+
+        let statement = &program.statements[0];
+        let span = statement.span();
+        assert_eq!((span.start.offset, span.end.offset), (0, test_str.len()));
+
+        if let Statement::AssignStatement { expression, .. } = statement {
+            let span = expression.span();
+            assert_eq!(
+                &test_str[span.start.offset..span.end.offset],
+                "foo(1, 2) + 3"
+            );
+
+            if let Expression::InfixExpression { left, .. } = &**expression {
+                let span = left.span();
+                assert_eq!(&test_str[span.start.offset..span.end.offset], "foo(1, 2)");
+            } else {
+                panic!("expected an infix expression, got {:?}", expression);
+            }
+        } else {
+            panic!("expected an assignment, got {:?}", statement);
+        }
+    }
+
+    #[test]
+    fn boolean_and_nil_literals() {
+        let test_str = "flags := [true, false, nil, x1]";
+
+        let (tokens, _) = Lexer::new(test_str).tokenize();
+        let program = parse_clean(tokens);
+
+        if let Statement::AssignStatement { expression, .. } = &program.statements[0] {
+            if let Expression::ArrayLiteral { elements, .. } = &**expression {
+                assert!(matches!(
+                    *elements[0],
+                    Expression::BooleanLiteral {
+                        token: Token {
+                            kind: TokenType::True,
+                            ..
+                        },
+                        ..
+                    }
+                ));
+                assert!(matches!(
+                    *elements[1],
+                    Expression::BooleanLiteral {
+                        token: Token {
+                            kind: TokenType::False,
+                            ..
+                        },
+                        ..
+                    }
+                ));
+                assert!(matches!(*elements[2], Expression::NilLiteral { .. }));
+                assert!(matches!(
+                    &*elements[3],
+                    Expression::NormalIdentifier { token, .. } if token.kind == TokenType::Ident("x1")
+                ));
+                return;
+            }
+        }
+        panic!("expected an array assignment, got {:?}", program);
+    }
+
+    #[test]
+    fn infix_operators() {
+        let cases = [
+            ("a + b", "(Plus a b)"),
+            ("a - b", "(Minus a b)"),
+            ("a * b", "(Asterisk a b)"),
+            ("a / b", "(Slash a b)"),
+            ("a % b", "(Percent a b)"),
+            ("a ** b", "(DoubleAsterisk a b)"),
+            ("a == b", "(DoubleEqual a b)"),
+            ("a != b", "(BangEqual a b)"),
+            ("a < b", "(LT a b)"),
+            ("a > b", "(GT a b)"),
+            ("a <= b", "(LTEq a b)"),
+            ("a >= b", "(GTEq a b)"),
+            ("a && b", "(And a b)"),
+            ("a || b", "(Or a b)"),
+            ("a & b", "(Ampersand a b)"),
+            ("a | b", "(Bar a b)"),
+            ("a ^ b", "(Caret a b)"),
+            ("a << b", "(ShiftLeft a b)"),
+            ("a >> b", "(ShiftRight a b)"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn operator_precedence_and_associativity() {
+        let cases = [
+            ("a + b * c", "(Plus a (Asterisk b c))"),
+            ("a - b - c", "(Minus (Minus a b) c)"),
+            ("a % b * c", "(Asterisk (Percent a b) c)"),
+            ("a ** b ** c", "(DoubleAsterisk a (DoubleAsterisk b c))"),
+            ("a * b ** c", "(Asterisk a (DoubleAsterisk b c))"),
+            ("a << b + c", "(ShiftLeft a (Plus b c))"),
+            ("a & b | c ^ d", "(Bar (Ampersand a b) (Caret c d))"),
+            ("a | b == c", "(DoubleEqual (Bar a b) c)"),
+            ("a < b == c > d", "(DoubleEqual (LT a b) (GT c d))"),
+            ("a || b && c", "(Or a (And b c))"),
+            ("a && b || c", "(Or (And a b) c)"),
+            (
+                "a == b && c != d",
+                "(And (DoubleEqual a b) (BangEqual c d))",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn prefix_operators() {
+        let cases = [
+            ("-x", "(Minus x)"),
+            ("-3.5", "(Minus 3.5)"),
+            ("!done", "(Bang done)"),
+            ("~mask", "(Tilde mask)"),
+            ("- -x", "(Minus (Minus x))"),
+            ("!!true", "(Bang (Bang true))"),
+            ("-a * b", "(Asterisk (Minus a) b)"),
+            ("!a == b", "(DoubleEqual (Bang a) b)"),
+            ("~a & b", "(Ampersand (Tilde a) b)"),
+            ("a - -b", "(Minus a (Minus b))"),
+            ("a * -b", "(Asterisk a (Minus b))"),
+            ("-f(x)", "(Minus f(x))"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+
+        assert_eq!(diagnostics("x := -").len(), 1);
+    }
+
+    #[test]
+    fn most_negative_integer() {
+        let cases = [
+            ("-9223372036854775808", "-9223372036854775808"),
+            ("-0x8000000000000000", "-9223372036854775808"),
+            (
+                "a * -9223372036854775808",
+                "(Asterisk a -9223372036854775808)",
+            ),
+            (
+                "-9223372036854775808 ** 2",
+                "(DoubleAsterisk -9223372036854775808 2)",
+            ),
+            (
+                "x match { -9223372036854775808 -> a }",
+                "(Match -9223372036854775808 -> [a])",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+
+        let (tokens, _) = Lexer::new("x := -9223372036854775808").tokenize();
+        parse_clean(tokens);
+    }
+
+    #[test]
+    fn if_expressions() {
+        let cases = [
+            ("if a { b }", "(If a [b])"),
+            ("if a { b } else { c }", "(If a [b] [c])"),
+            (
+                "if a { b } else if c { d } else { e }",
+                "(If a [b] (If c [d] [e]))",
+            ),
+            (
+                "if a < b { -a; b } else {}",
+                "(If (LT a b) [(Minus a); b] [])",
+            ),
+            ("if a { b }\nelse { c }", "(If a [b] [c])"),
+            ("(if a { 1 } else { 2 }) + 3", "(Plus (If a [1] [2]) 3)"),
+            (
+                "if a {\n  b\n}\n\nelse if c {\n  d\n}",
+                "(If a [b] (If c [d]))",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+
+        let (tokens, _) = Lexer::new("x := if a { 1 }\ny := 2").tokenize();
+        let program = parse_clean(tokens);
+        assert_eq!(program.statements.len(), 2);
+    }
+
+    #[test]
+    fn dangling_else_is_rejected() {
+        let inputs = [
+            "else { a }",
+            "if a { b }; else { c }",
+            "a\nelse { b }",
+            "if a { b } else c",
+        ];
+
+        for input in inputs.iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn map_literals() {
+        let cases = [
+            ("{}", "{}"),
+            (
+                r#"{ "name": "clay", version: 1, [computed]: x }"#,
+                r#"{"name": "clay", "version": 1, [computed]: x}"#,
+            ),
+            ("{ b: 1, a: 2, }", r#"{"b": 1, "a": 2}"#),
+            (
+                "{\n  a: 1\n  [k + 1]: {x: y},\n}",
+                r#"{"a": 1, [(Plus k 1)]: {"x": y}}"#,
+            ),
+            ("|x| -> { a: 1 }", r#"(Function [{"a": 1}])"#),
+            ("|x| -> { [a]: 1 }", r#"(Function [{[a]: 1}])"#),
+            ("|x| -> { a }", "(Function [a])"),
+            ("|x| -> { [a] }", "(Function [[a]])"),
+            ("|x| -> {}", "(Function [])"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn duplicate_map_keys_are_reported() {
+        let (tokens, _) =
+            Lexer::new(r#"m := { a: 1, "a": 2, [a]: 3, b: 4, a: 5 }; n := 1"#).tokenize();
+        let result = Parser::new(tokens).parse_program();
+
+        assert_eq!(result.program.statements.len(), 2);
+        let columns = result
+            .diagnostics
+            .iter()
+            .map(|error| error.span.start.column)
+            .collect::<Vec<_>>();
+        assert_eq!(columns, vec![13, 35]);
+
+        for input in ["{ a 1 }", "{ a: 1 b: 2 }", "{ 1: 2 }", "{ a: 1"].iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn index_and_slice_expressions() {
+        let cases = [
+            ("z[0]", "(Index z 0)"),
+            (r#"m["key"]"#, r#"(Index m "key")"#),
+            ("a[i + 1][j]", "(Index (Index a (Plus i 1)) j)"),
+            ("f(x)[0]", "(Index f(x) 0)"),
+            ("-a[0] * 2", "(Asterisk (Minus (Index a 0)) 2)"),
+            ("[1, 2][0]", "(Index [1, 2] 0)"),
+            ("a[1:3]", "(Slice a 1 3 _)"),
+            ("a[:2]", "(Slice a _ 2 _)"),
+            ("a[1:]", "(Slice a 1 _ _)"),
+            ("a[:]", "(Slice a _ _ _)"),
+            ("a[::-1]", "(Slice a _ _ (Minus 1))"),
+            ("a[i:i + n:2]", "(Slice a i (Plus i n) 2)"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+
+        for input in ["a[]", "a[1", "a[1:2:3:4]", "a[1 2]"].iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn index_assignment() {
+        let input = "a[0] = 5\nm[\"k\"] += 1\na.b[i][j] = x * 2\nf(x)[0] -= 1\na[0] == 5";
+        let (tokens, _) = Lexer::new(input).tokenize();
+        let program = parse_clean(tokens);
+
+        let statements = program
+            .statements
+            .iter()
+            .map(statement_sexp)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                "(Equal (Index a 0) 5)",
+                r#"(PlusEqual (Index m "k") 1)"#,
+                "(Equal (Index (Index a.b i) j) (Asterisk x 2))",
+                "(MinusEqual (Index f(x) 0) 1)",
+                "(DoubleEqual (Index a 0) 5)",
+            ]
+        );
+        assert!(matches!(
+            program.statements[0],
+            Statement::UpdateStatement { .. }
+        ));
+    }
+
+    #[test]
+    fn member_access_on_any_expression() {
+        let cases = [
+            ("a.b.c", "a.b.c"),
+            ("make().name", "make().name"),
+            ("[1, 2, 3].len()", "[1, 2, 3].len()"),
+            (r#""abc".upper()"#, r#""abc".upper()"#),
+            ("(a + b).x", "(Plus a b).x"),
+            ("a.b(c).d[0].e", "(Index a.b(c).d 0).e"),
+            ("-a.b ** 2", "(DoubleAsterisk (Minus a.b) 2)"),
+            ("{x: 1}.x", r#"{"x": 1}.x"#),
+            ("items\n  .filter(f)\n  .len()", "items.filter(f).len()"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+
+        let (tokens, _) = Lexer::new("make().name = 1\na.b.c = 2").tokenize();
+        let program = parse_clean(tokens);
+        let statements = program
+            .statements
+            .iter()
+            .map(statement_sexp)
+            .collect::<Vec<_>>();
+        assert_eq!(statements, vec!["(Equal make().name 1)", "(Equal a.b.c 2)"]);
+
+        for input in ["a.", "a.1", "a.b := 1", "f() = 1", "a + b = 1"].iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn loops() {
+        let cases = [
+            (
+                "while i < 10 { i += 1 }",
+                "(While (LT i 10) [(PlusEqual i 1)])",
+            ),
+            ("for x in xs { f(x) }", "(For x xs [f(x)])"),
+            ("for k, v in m.items() {}", "(For k, v m.items() [])"),
+            ("loop { break }", "(Loop [(Break)])"),
+            (
+                "loop {\n  if done { break result }\n  continue\n}",
+                "(Loop [(If done [(Break result)]); (Continue)])",
+            ),
+            (
+                "outer: for row in rows {\n  for x in row { continue outer }\n}",
+                "(outer: For row rows [(For x row [(outer: Continue)])])",
+            ),
+            (
+                "outer: loop { loop { break outer 1 + 2 } }",
+                "(outer: Loop [(Loop [(outer: Break (Plus 1 2))])])",
+            ),
+            ("loop { break outer }", "(Loop [(Break outer)])"),
+            (
+                "loop { f(|x| -> x); break }",
+                "(Loop [f((Function [x])); (Break)])",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            let (tokens, errors) = Lexer::new(input).tokenize();
+            assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+            let statements = parse_clean(tokens)
+                .statements
+                .iter()
+                .map(statement_sexp)
+                .collect::<Vec<_>>();
+            assert_eq!(statements.join("; "), *expected, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn return_value_is_optional() {
+        let cases = [
+            ("return", "(Return)"),
+            ("return\nx", "(Return); x"),
+            ("return; x", "(Return); x"),
+            ("return a + 1", "(Plus a 1)"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            let (tokens, errors) = Lexer::new(input).tokenize();
+            assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+            let statements = parse_clean(tokens)
+                .statements
+                .iter()
+                .map(statement_sexp)
+                .collect::<Vec<_>>();
+            assert_eq!(statements.join("; "), *expected, "parsing {}", input);
+        }
+
+        let (tokens, _) = Lexer::new("f := |x| -> { return }").tokenize();
+        parse_clean(tokens);
+    }
+
+    #[test]
+    fn break_outside_a_loop_is_reported() {
+        let inputs = [
+            "break",
+            "continue",
+            "if a { break 1 }",
+            "loop { f := |x| -> { break } }",
+            "loop { continue outer }",
+        ];
+
+        for input in inputs.iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn malformed_loops_are_reported() {
+        for input in ["for a, b, c in xs {}", "while a b", "for 1 in xs {}"].iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn match_patterns() {
+        let cases = [
+            (
+                r#"x match { 0 -> a, -1 -> b, 2.5 -> c, "s" -> d, true -> e, nil -> f, _ -> g }"#,
+                r#"(Match 0 -> [a], (Minus 1) -> [b], 2.5 -> [c], "s" -> [d], true -> [e], nil -> [f], _ -> [g])"#,
+            ),
+            (
+                "x match { n if n > 1 -> n, n -> 0 }",
+                "(Match n if (GT n 1) -> [n], n -> [0])",
+            ),
+            (
+                "x match { [] -> 0, [first, ...rest] -> 1, [_, ..., last] -> 2, [...] -> 3 }",
+                "(Match [] -> [0], [first, ...rest] -> [1], [_, ..., last] -> [2], [...] -> [3])",
+            ),
+            (
+                r#"x match { { name, "age": 1..=9, pos: [a, b] } -> name }"#,
+                r#"(Match {"name": name, "age": (1..=9), "pos": [a, b]} -> [name])"#,
+            ),
+            (
+                r#"x match { -5..0 | 10..=20 -> a, "a"..="z" -> b }"#,
+                r#"(Match (((Minus 5)..0) | (10..=20)) -> [a], ("a"..="z") -> [b])"#,
+            ),
+            (
+                "x match { 1 | 2, 3 -> a, [1 | 2, _] -> b, _ if y -> c }",
+                "(Match (1 | 2 | 3) -> [a], [(1 | 2), _] -> [b], _ if y -> [c])",
+            ),
+            (
+                "x match {\n  {\n    a: 1,\n    b\n  } -> a\n  _ -> { b }\n}",
+                r#"(Match {"a": 1, "b": b} -> [a], _ -> [b])"#,
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(parse_sexp(input), *expected, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn malformed_patterns_are_reported() {
+        let cases = [
+            ("x match { a + 1 -> a }", "Expected 'Arrow'"),
+            ("x match { f(a) -> a }", "Expected 'Arrow'"),
+            (
+                "x match { ...rest -> a }",
+                "only appear directly inside an array",
+            ),
+            ("x match { [...a, ...b] -> a }", "Only one '...'"),
+            ("x match { [a b] -> a }", "closing an array pattern"),
+            ("x match { { [k]: v } -> a }", "Keys are names or strings"),
+            (r#"x match { { "k" } -> a }"#, "following a string key"),
+            ("x match { 1.. -> a }", "to end the range"),
+            ("x match { true..false -> a }", "must start with a number"),
+            ("x match { - a -> a }", "number after '-'"),
+            (r#"x match { "{a}" -> a }"#, "interpolated string"),
+            ("x match { (a) -> a }", "Expected a pattern"),
+            ("x match { a if -> a }", "as the guard"),
+            ("x match { 1 -> a, _ -> b, _ -> c }", "unreachable"),
+            ("x match { _ -> a, 1 -> b }", "unreachable"),
+            ("x match { n -> a, _ -> b }", "unreachable"),
+            ("x match { 1, _ -> a, 2 -> b }", "unreachable"),
+            ("x match { [a] | [b] -> a }", "bind the same names"),
+            ("x match { 5, x -> a }", "bind the same names"),
+            ("x match { _, x -> a }", "bind the same names"),
+        ];
+
+        for (input, message) in cases.iter() {
+            let errors = diagnostics(input);
+            assert_eq!(errors.len(), 1, "parsing {}", input);
+            assert!(
+                errors[0].message.contains(message),
+                "parsing {}: {}",
+                input,
+                errors[0].message
+            );
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let input = "a += b\na -= b * 2\na.b *= 2\nm[\"k\"] /= 2\nf(x).y[0] %= 3\na = 1";
+        let (tokens, _) = Lexer::new(input).tokenize();
+        let program = parse_clean(tokens);
+
+        let statements = program
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::UpdateStatement {
+                    operator,
+                    ident,
+                    expression,
+                    ..
+                } => format!("({:?} {} {})", operator, sexp(ident), sexp(expression)),
+                other => panic!("expected an update, got {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                "(Some(Plus) a b)",
+                "(Some(Minus) a (Asterisk b 2))",
+                "(Some(Asterisk) a.b 2)",
+                r#"(Some(Slash) (Index m "k") 2)"#,
+                "(Some(Percent) (Index f(x).y 0) 3)",
+                "(None a 1)",
+            ]
+        );
+
+        let inputs = [
+            "f() += 1",
+            "a + b -= 1",
+            "1 *= 2",
+            "a += b += 1",
+            "x := a += 1",
+            "a +=",
+        ];
+        for input in inputs.iter() {
+            assert_eq!(diagnostics(input).len(), 1, "parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn every_operator_pair_groups_by_precedence() {
+        // Loosest level first; `true` marks right-associative levels.
+        let levels: &[(&[&str], bool)] = &[
+            (&["||"], false),
+            (&["&&"], false),
+            (&["==", "!="], false),
+            (&["<", ">", "<=", ">="], false),
+            (&["|"], false),
+            (&["^"], false),
+            (&["&"], false),
+            (&["<<", ">>"], false),
+            (&["+", "-"], false),
+            (&["*", "/", "%"], false),
+            (&["**"], true),
+        ];
+        let operators = levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, (ops, right))| ops.iter().map(move |op| (*op, level, *right)))
+            .collect::<Vec<_>>();
+        let kind = |op: &str| {
+            let (tokens, _) = Lexer::new(op).tokenize();
+            format!("{:?}", tokens[0].kind)
+        };
+
+        for &(first, first_level, right) in operators.iter() {
+            for &(second, second_level, _) in operators.iter() {
+                let (first_kind, second_kind) = (kind(first), kind(second));
+                let groups_left =
+                    first_level > second_level || (first_level == second_level && !right);
+                let expected = if groups_left {
+                    format!("({} ({} a b) c)", second_kind, first_kind)
+                } else {
+                    format!("({} a ({} b c))", first_kind, second_kind)
+                };
+
+                let input = format!("a {} b {} c", first, second);
+                assert_eq!(parse_sexp(&input), expected, "parsing {}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn semicolons_separate_statements() {
+        let (tokens, _) = Lexer::new("a; b;; c").tokenize();
+        let program = parse_clean(tokens);
+
+        assert_eq!(program.statements.len(), 3);
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        assert_eq!(
+            parse_sexp(r#""Hello {name}, you are {age + 1}""#),
+            r#"(Interpolate "Hello " name ", you are " (Plus age 1))"#
+        );
+        assert_eq!(
+            parse_sexp(r#""{a}{"b{c}"}{{}}" + d"#),
+            r#"(Plus (Interpolate a (Interpolate "b" c) "{}") d)"#
+        );
+
+        let (tokens, _) = Lexer::new(r#"x := "n = {n}!""#).tokenize();
+        let program = parse_clean(tokens);
+        let span = program.statements[0].span();
+        assert_eq!((span.start.offset, span.end.offset), (0, 15));
+    }
+
+    #[test]
+    fn newlines_terminate_statements() {
+        let count = |input| {
+            let (tokens, errors) = Lexer::new(input).tokenize();
+            assert!(errors.is_empty(), "unexpected errors: {:?}", errors);
+            parse_clean(tokens).statements.len()
+        };
+
+        assert_eq!(count("f\n(x)"), 2);
+        assert_eq!(count("f(x)"), 1);
+        assert_eq!(count("a := 1; b := 2; c"), 3);
+        assert_eq!(
+            count("a := 1 +\n  2\nb := (1\n  + 2)\n\n\nc := [1,\n  2]\n"),
+            3
+        );
+        assert_eq!(count("x := obj\n  .field\n  .other"), 1);
+        assert_eq!(count("f := |n| -> {\n  a := n\n\n  a\n}\nf(1)"), 2);
+        assert_eq!(count("g := |n| -> n * 2\ng(1)"), 2);
+        assert_eq!(
+            count("y := x match {\n  1 -> \"one\"\n  2, 3 -> \"few\",\n  _ -> \"many\",\n}\nz"),
+            2
+        );
+    }
+
+    #[test]
+    fn errors_are_reported_and_parsing_recovers() {
+        let input = "a := 1 + * 2\nb := 2\nc := f(1 2)\nd := [1, 2]\nimport 5; e := 3";
+        let (tokens, _) = Lexer::new(input).tokenize();
+        let result = Parser::new(tokens).parse_program();
+
+        let lines = result
+            .diagnostics
+            .iter()
+            .map(|d| d.span.start.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 3, 5], "{:#?}", result.diagnostics);
+
+        let statements = &result.program.statements;
+        assert_eq!(statements.len(), 6);
+        assert!(matches!(statements[0], Statement::AssignStatement { .. }));
+        assert!(matches!(statements[1], Statement::AssignStatement { .. }));
+        assert!(matches!(statements[2], Statement::AssignStatement { .. }));
+        assert!(matches!(statements[3], Statement::AssignStatement { .. }));
+        assert!(matches!(statements[4], Statement::Error { .. }));
+        assert!(matches!(statements[5], Statement::AssignStatement { .. }));
+
+        // `a := 1 + * 2` keeps its statement, with a placeholder operand.
+        if let Statement::AssignStatement { expression, .. } = &statements[0] {
+            assert!(matches!(
+                &**expression,
+                Expression::InfixExpression { right, .. }
+                    if matches!(**right, Expression::Error { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn errors_inside_blocks_recover_inside_the_block() {
+        let input = "f := |x| -> {\n  y := )\n  y\n}\ng := 1 2";
+        let (tokens, _) = Lexer::new(input).tokenize();
+        let result = Parser::new(tokens).parse_program();
+
+        assert_eq!(result.diagnostics.len(), 2, "{:#?}", result.diagnostics);
+        assert_eq!(result.diagnostics[0].span.start.line, 2);
+        assert_eq!(result.diagnostics[1].span.start.line, 5);
+        assert!(matches!(
+            result.program.statements[0],
+            Statement::AssignStatement { .. }
+        ));
+        assert_eq!(result.program.statements.len(), 2);
+    }
+
+    #[test]
+    fn truncated_input_never_panics() {
+        let input = "import io\nf := |a, b| -> {\n  x := [a, (b + 1) ** 2]\n  return x match { 1, 2 -> \"{a}\", _ -> { io.out(b) } }\n}\nf(1, 2)";
+        let (tokens, _) = Lexer::new(input).tokenize();
+
+        for end in 0..=tokens.len() {
+            let result = Parser::new(tokens[..end].to_vec()).parse_program();
+            if end == tokens.len() {
+                assert!(result.diagnostics.is_empty(), "{:#?}", result.diagnostics);
+            }
+        }
+    }
 }
@@ -1,5 +1,7 @@
+use std::borrow::Cow;
+
 use colored::*;
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType<'a> {
     RParen,   // )
     LParen,   // (
@@ -13,6 +15,7 @@ pub enum TokenType<'a> {
     Minus,
     Slash,
     Asterisk,
+    DoubleAsterisk,
     Equal,
     DoubleEqual,
     Bang,
@@ -25,63 +28,237 @@ pub enum TokenType<'a> {
     Underscore,
 
     Period,
+    DotDot,
+    DotDotEqual,
+    Ellipsis,
     Semicolon,
     Ampersand,
     And,
     Bar,
     Or,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
     PlusEqual,
     MinusEqual,
     SlashEqual,
     AsteriskEqual,
+    PercentEqual,
     Comma,
     Colon,
     ColonEqual,
 
-    Integer(usize),
-    Float(f32),
-    String(&'a str),
+    Integer(i64),
+    Float(f64),
+    String(Cow<'a, str>),
+    // An interpolated string is split around its `{...}` parts, so
+    // `"a {x} b {y} c"` lexes as StringHead("a "), x, StringMiddle(" b "), y,
+    // StringTail(" c"). The braces belong to the string part tokens.
+    StringHead(Cow<'a, str>),
+    StringMiddle(Cow<'a, str>),
+    StringTail(Cow<'a, str>),
+
+    // Trivia, only produced on request
+    Comment(&'a str),
+    Whitespace(&'a str),
+    // Produced where a newline ends a statement, and for every newline on request
+    Newline,
 
     // Keywords
     Ident(&'a str),
     Match,
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Loop,
+    Break,
+    Continue,
     Import,
     Return,
+    True,
+    False,
+    Nil,
 }
 
 impl<'a> TokenType<'a> {
-    pub fn match_keyword(string: &'a str) -> TokenType {
+    pub fn match_keyword(string: &'a str) -> TokenType<'a> {
         match string {
             "match" => TokenType::Match,
+            "if" => TokenType::If,
+            "else" => TokenType::Else,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
+            "loop" => TokenType::Loop,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "import" => TokenType::Import,
             "return" => TokenType::Return,
+            "true" => TokenType::True,
+            "false" => TokenType::False,
+            "nil" => TokenType::Nil,
             _ => TokenType::Ident(string),
         }
     }
+
+    /// Whether a statement can end with this token, making a newline after
+    /// it significant.
+    pub fn can_end_statement(&self) -> bool {
+        self.ends_operand()
+            || matches!(
+                self,
+                TokenType::Return | TokenType::Break | TokenType::Continue
+            )
+    }
+
+    /// Whether this token can be the last one of an operand, such as `a` or
+    /// `)`, so that an operator following it is binary.
+    pub fn ends_operand(&self) -> bool {
+        matches!(
+            self,
+            TokenType::RParen
+                | TokenType::RBrace
+                | TokenType::RBracket
+                | TokenType::Underscore
+                | TokenType::Integer(_)
+                | TokenType::Float(_)
+                | TokenType::String(_)
+                | TokenType::StringTail(_)
+                | TokenType::Ident(_)
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+        )
+    }
+
+    /// The binary operator a compound assignment such as `+=` applies.
+    pub fn compound_operator(&self) -> Option<TokenType<'a>> {
+        match self {
+            TokenType::PlusEqual => Some(TokenType::Plus),
+            TokenType::MinusEqual => Some(TokenType::Minus),
+            TokenType::AsteriskEqual => Some(TokenType::Asterisk),
+            TokenType::SlashEqual => Some(TokenType::Slash),
+            TokenType::PercentEqual => Some(TokenType::Percent),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds this token type on top of another copy of the source, passing
+    /// every slice it borrows through `map`. Used to carry tokens over to an
+    /// edited source without lexing them again.
+    pub fn map_source<'b>(&self, mut map: impl FnMut(&'a str) -> &'b str) -> TokenType<'b> {
+        match self {
+            TokenType::RParen => TokenType::RParen,
+            TokenType::LParen => TokenType::LParen,
+            TokenType::RBrace => TokenType::RBrace,
+            TokenType::LBrace => TokenType::LBrace,
+            TokenType::RBracket => TokenType::RBracket,
+            TokenType::LBracket => TokenType::LBracket,
+            TokenType::Percent => TokenType::Percent,
+            TokenType::Plus => TokenType::Plus,
+            TokenType::Minus => TokenType::Minus,
+            TokenType::Slash => TokenType::Slash,
+            TokenType::Asterisk => TokenType::Asterisk,
+            TokenType::DoubleAsterisk => TokenType::DoubleAsterisk,
+            TokenType::Equal => TokenType::Equal,
+            TokenType::DoubleEqual => TokenType::DoubleEqual,
+            TokenType::Bang => TokenType::Bang,
+            TokenType::BangEqual => TokenType::BangEqual,
+            TokenType::LT => TokenType::LT,
+            TokenType::LTEq => TokenType::LTEq,
+            TokenType::GT => TokenType::GT,
+            TokenType::GTEq => TokenType::GTEq,
+            TokenType::Arrow => TokenType::Arrow,
+            TokenType::Underscore => TokenType::Underscore,
+            TokenType::Period => TokenType::Period,
+            TokenType::DotDot => TokenType::DotDot,
+            TokenType::DotDotEqual => TokenType::DotDotEqual,
+            TokenType::Ellipsis => TokenType::Ellipsis,
+            TokenType::Semicolon => TokenType::Semicolon,
+            TokenType::Ampersand => TokenType::Ampersand,
+            TokenType::And => TokenType::And,
+            TokenType::Bar => TokenType::Bar,
+            TokenType::Or => TokenType::Or,
+            TokenType::Caret => TokenType::Caret,
+            TokenType::Tilde => TokenType::Tilde,
+            TokenType::ShiftLeft => TokenType::ShiftLeft,
+            TokenType::ShiftRight => TokenType::ShiftRight,
+            TokenType::PlusEqual => TokenType::PlusEqual,
+            TokenType::MinusEqual => TokenType::MinusEqual,
+            TokenType::SlashEqual => TokenType::SlashEqual,
+            TokenType::AsteriskEqual => TokenType::AsteriskEqual,
+            TokenType::PercentEqual => TokenType::PercentEqual,
+            TokenType::Comma => TokenType::Comma,
+            TokenType::Colon => TokenType::Colon,
+            TokenType::ColonEqual => TokenType::ColonEqual,
+            TokenType::Integer(n) => TokenType::Integer(*n),
+            TokenType::Float(n) => TokenType::Float(*n),
+            TokenType::String(value) => TokenType::String(map_cow(value.clone(), &mut map)),
+            TokenType::StringHead(value) => TokenType::StringHead(map_cow(value.clone(), &mut map)),
+            TokenType::StringMiddle(value) => {
+                TokenType::StringMiddle(map_cow(value.clone(), &mut map))
+            }
+            TokenType::StringTail(value) => TokenType::StringTail(map_cow(value.clone(), &mut map)),
+            TokenType::Comment(text) => TokenType::Comment(map(text)),
+            TokenType::Whitespace(text) => TokenType::Whitespace(map(text)),
+            TokenType::Ident(name) => TokenType::Ident(map(name)),
+            TokenType::Newline => TokenType::Newline,
+            TokenType::Match => TokenType::Match,
+            TokenType::If => TokenType::If,
+            TokenType::Else => TokenType::Else,
+            TokenType::While => TokenType::While,
+            TokenType::For => TokenType::For,
+            TokenType::In => TokenType::In,
+            TokenType::Loop => TokenType::Loop,
+            TokenType::Break => TokenType::Break,
+            TokenType::Continue => TokenType::Continue,
+            TokenType::Import => TokenType::Import,
+            TokenType::Return => TokenType::Return,
+            TokenType::True => TokenType::True,
+            TokenType::False => TokenType::False,
+            TokenType::Nil => TokenType::Nil,
+        }
+    }
+}
+
+fn map_cow<'a, 'b>(value: Cow<'a, str>, map: &mut impl FnMut(&'a str) -> &'b str) -> Cow<'b, str> {
+    match value {
+        Cow::Borrowed(value) => Cow::Borrowed(map(value)),
+        Cow::Owned(value) => Cow::Owned(value),
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Token<'a> {
     pub kind: TokenType<'a>,
-    pub position: Position,
+    pub span: Span,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(kind: TokenType, position: Position) -> Token {
-        Token { kind, position }
+    pub fn new(kind: TokenType, span: Span) -> Token {
+        Token { kind, span }
     }
 
-    pub fn from_keyword(keyword: &'a str, position: Position) -> Token {
+    pub fn from_keyword(keyword: &'a str, span: Span) -> Token<'a> {
         Token {
             kind: TokenType::match_keyword(keyword),
 
-            position,
+            span,
         }
     }
 }
 impl<'a> std::fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({:?} @ {})", self.kind, self.position)
+        write!(f, "({:?} @ {})", self.kind, self.span)
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.start)
     }
 }
 
@@ -96,15 +273,61 @@ impl std::fmt::Display for Position {
         )
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     pub line: usize,
+    /// Column in chars (Unicode scalar values) from the start of the line.
     pub column: usize,
+    /// Column in UTF-16 code units, as expected by LSP-style editors.
+    pub utf16_column: usize,
     pub char: usize,
+    /// Byte offset into the source, used for slicing.
+    pub offset: usize,
 }
 
 impl Position {
-    pub fn new(line: usize, column: usize, char: usize) -> Position {
-        Position { line, column, char }
+    pub fn new(
+        line: usize,
+        column: usize,
+        utf16_column: usize,
+        char: usize,
+        offset: usize,
+    ) -> Position {
+        Position {
+            line,
+            column,
+            utf16_column,
+            char,
+            offset,
+        }
+    }
+}
+
+/// The source range of a token or syntax node. `end` is exclusive: it is the
+/// position just past the last character covered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub file_id: usize,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(file_id: usize, start: Position, end: Position) -> Span {
+        Span {
+            file_id,
+            start,
+            end,
+        }
+    }
+
+    /// The text this span covers in `source`.
+    pub fn source_text<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start.offset..self.end.offset]
+    }
+
+    /// A span running from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.file_id, self.start, other.end)
     }
 }